@@ -0,0 +1,203 @@
+use crate::spacerock::{spice_offset, SpaceRock};
+use crate::frame::Frame;
+use crate::keplerorbit::KeplerOrbit;
+use crate::calc_xyz_from_kep::calc_xyz_from_kep;
+use crate::observatory::Observatory;
+use crate::error::{Result, SpaceRocksError};
+
+use nalgebra::Vector3;
+#[cfg(feature = "rayon")]
+use rayon::prelude::*;
+
+use std::collections::HashMap;
+
+// The single-rock operations over whole populations. Cspice isn't thread safe, so everything it provides
+// (frame rotations, origins, observers) is worked out once per distinct epoch up front, and only the
+// per-rock arithmetic is spread across threads, with the rayon feature; without it the same runs serially.
+
+#[cfg(feature = "rayon")]
+fn each<T: Send, U: Sync>(items: &mut [T], with: &[U], f: impl Fn(&mut T, &U) + Sync + Send) {
+    items.par_iter_mut().zip(with).for_each(|(item, with)| f(item, with));
+}
+
+#[cfg(not(feature = "rayon"))]
+fn each<T, U>(items: &mut [T], with: &[U], f: impl Fn(&mut T, &U)) {
+    items.iter_mut().zip(with).for_each(|(item, with)| f(item, with));
+}
+
+#[cfg(feature = "rayon")]
+fn map<T: Sync, U: Send>(items: &[T], f: impl Fn(&T) -> U + Sync + Send) -> Vec<U> {
+    items.par_iter().map(f).collect()
+}
+
+#[cfg(not(feature = "rayon"))]
+fn map<T, U>(items: &[T], f: impl Fn(&T) -> U) -> Vec<U> {
+    items.iter().map(f).collect()
+}
+
+// The index of a value among those seen so far, adding it if it's new. There are only ever a few frames
+// and origins, so a scan is cheaper than hashing a name for every rock.
+fn intern<T: PartialEq + Clone, Q: PartialEq<T> + ?Sized + ToOwned<Owned = T>>(seen: &mut Vec<T>, value: &Q) -> usize {
+    match seen.iter().position(|other| value == other) {
+        Some(index) => index,
+        None => {
+            seen.push(value.to_owned());
+            seen.len() - 1
+        }
+    }
+}
+
+// Work something out once for each distinct key, serially, noting which one each rock needs.
+fn tabulate<K: std::hash::Hash + Eq, V>(keys: impl Iterator<Item = Option<K>>, mut value: impl FnMut(&K) -> Result<V>) -> Result<(Vec<V>, Vec<Option<usize>>)> {
+    let mut indices: HashMap<K, usize> = HashMap::new();
+    let mut values = Vec::new();
+    let mut which = Vec::new();
+    for key in keys {
+        which.push(match key {
+            Some(key) => Some(match indices.get(&key) {
+                Some(&index) => index,
+                None => {
+                    values.push(value(&key)?);
+                    indices.insert(key, values.len() - 1);
+                    values.len() - 1
+                }
+            }),
+            None => None,
+        });
+    }
+    Ok((values, which))
+}
+
+// Move each rock into its own frame, with one transformation per frame pair and epoch.
+fn change_frames(rocks: &mut [SpaceRock], frames: &[&Frame]) -> Result<()> {
+    let mut seen: Vec<Frame> = Vec::new();
+    let keys: Vec<Option<(usize, usize, u64)>> = rocks.iter().zip(frames).map(|(rock, frame)| {
+        (rock.frame != **frame).then(|| (intern(&mut seen, &rock.frame), intern(&mut seen, *frame), rock.epoch.to_bits()))
+    }).collect();
+    let (transformations, which) = tabulate(keys.into_iter(), |&(from, to, epoch)| {
+        seen[from].transformation_to(&seen[to], f64::from_bits(epoch))
+    })?;
+    let targets: Vec<Option<(usize, &Frame)>> = which.into_iter().zip(frames).map(|(index, frame)| index.map(|index| (index, *frame))).collect();
+    each(rocks, &targets, |rock, target| {
+        if let Some((index, frame)) = target {
+            rock.transform(&transformations[*index], frame);
+        }
+    });
+    Ok(())
+}
+
+pub fn change_frame(rocks: &mut [SpaceRock], frame: &Frame) -> Result<()> {
+    change_frames(rocks, &vec![frame; rocks.len()])
+}
+
+// As SpaceRock::change_origin, with the origin's state looked up once per epoch.
+pub fn change_origin(rocks: &mut [SpaceRock], origin: &str) -> Result<()> {
+    let frames: Vec<Frame> = rocks.iter().map(|rock| rock.frame.clone()).collect();
+    change_frame(rocks, &Frame::J2000)?;
+
+    let mut seen: Vec<String> = Vec::new();
+    let keys: Vec<Option<(usize, u64)>> = rocks.iter().map(|rock| {
+        (!rock.origin.eq_ignore_ascii_case(origin)).then(|| (intern(&mut seen, rock.origin.as_str()), rock.epoch.to_bits()))
+    }).collect();
+    let (offsets, which) = tabulate(keys.into_iter(), |&(from, epoch)| spice_offset(origin, &seen[from], f64::from_bits(epoch)))?;
+    each(rocks, &which, |rock, index| {
+        if let Some(index) = index {
+            let (position, velocity): &(Vector3<f64>, Vector3<f64>) = &offsets[*index];
+            rock.position -= position;
+            rock.velocity -= velocity;
+            rock.origin = origin.to_string();
+        }
+    });
+
+    change_frames(rocks, &frames.iter().collect::<Vec<_>>())
+}
+
+// As SpaceRock::analytic_propagate: a two-body drift about the barycentric mass, ignoring the planets, so
+// only good for short spans or distant rocks. Every rock must be about the SSB, and covariances are dropped.
+pub fn analytic_propagate(rocks: &mut [SpaceRock], epoch: f64) -> Result<()> {
+    if let Some(rock) = rocks.iter().find(|rock| !rock.origin.eq_ignore_ascii_case("SSB")) {
+        return Err(SpaceRocksError::InvalidInput(format!("{} must be barycentric to propagate analytically", rock.name)));
+    }
+    let frames: Vec<Frame> = rocks.iter().map(|rock| rock.frame.clone()).collect();
+    change_frame(rocks, &Frame::J2000)?;
+    let epochs = vec![epoch; rocks.len()];
+    each(rocks, &epochs, |rock, epoch| rock.drift(*epoch));
+    change_frames(rocks, &frames.iter().collect::<Vec<_>>())
+}
+
+// Right ascension and declination of each rock from one observer, e.g. an observatory at an exposure's epoch.
+pub fn observe(rocks: &mut [SpaceRock], observer: &SpaceRock) -> Result<Vec<[f64; 2]>> {
+    change_frame(rocks, &Frame::J2000)?;
+    Ok(map(rocks, |rock| rock.sky_position(observer)))
+}
+
+// As observe, from an observatory placed once at each epoch among the rocks.
+pub fn observe_from(rocks: &mut [SpaceRock], observatory: &Observatory) -> Result<Vec<[f64; 2]>> {
+    change_frame(rocks, &Frame::J2000)?;
+    let (observers, which) = tabulate(rocks.iter().map(|rock| Some(rock.epoch.to_bits())), |&epoch| observatory.at(f64::from_bits(epoch)))?;
+    let pairs: Vec<(&SpaceRock, usize)> = rocks.iter().zip(which).map(|(rock, index)| (rock, index.unwrap())).collect();
+    Ok(map(&pairs, |(rock, index)| rock.sky_position(&observers[*index])))
+}
+
+pub fn kepler_orbits(rocks: &[SpaceRock]) -> Vec<KeplerOrbit> {
+    map(rocks, SpaceRock::kepler_orbit)
+}
+
+// Rocks from orbits in J2000 about the SSB, paired with their names, all at one epoch.
+pub fn from_kepler_orbits(names: &[&str], orbits: &[KeplerOrbit], epoch: f64) -> Result<Vec<SpaceRock>> {
+    if names.len() != orbits.len() {
+        return Err(SpaceRocksError::InvalidInput(format!("{} names for {} orbits", names.len(), orbits.len())));
+    }
+    let pairs: Vec<(&&str, &KeplerOrbit)> = names.iter().zip(orbits).collect();
+    Ok(map(&pairs, |(name, orbit)| SpaceRock::from_state(name, calc_xyz_from_kep(**orbit), epoch)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Barycentric rocks in J2000 on a spread of orbits, so nothing here needs spice.
+    fn rocks() -> Vec<SpaceRock> {
+        (0..200).map(|i| {
+            let i = i as f64;
+            let orbit = KeplerOrbit::new(0.8 + 0.2 * i, 0.01 + 0.004 * i, 0.01 * i, 0.3 * i, 0.7 * i, 0.11 * i);
+            SpaceRock::from_state(&format!("rock {}", i), calc_xyz_from_kep(orbit), 2460000.5 + i)
+        }).collect()
+    }
+
+    #[test]
+    fn batches_match_the_rocks_one_at_a_time() {
+        let mut batch = rocks();
+        analytic_propagate(&mut batch, 2460500.5).unwrap();
+        let mut single = rocks();
+        for rock in single.iter_mut() {
+            rock.analytic_propagate(2460500.5).unwrap();
+        }
+        for (a, b) in batch.iter().zip(&single) {
+            assert_eq!((a.epoch, a.position, a.velocity), (b.epoch, b.position, b.velocity));
+        }
+
+        let orbits = kepler_orbits(&batch);
+        for (orbit, rock) in orbits.iter().zip(&single) {
+            let expected = rock.kepler_orbit();
+            assert_eq!((orbit.a, orbit.e, orbit.inc, orbit.arg, orbit.node, orbit.f),
+                       (expected.a, expected.e, expected.inc, expected.arg, expected.node, expected.f));
+        }
+
+        let names: Vec<String> = single.iter().map(|rock| rock.name.clone()).collect();
+        let names: Vec<&str> = names.iter().map(String::as_str).collect();
+        let rebuilt = from_kepler_orbits(&names, &orbits, 2460500.5).unwrap();
+        for (a, (orbit, name)) in rebuilt.iter().zip(orbits.iter().zip(&names)) {
+            let b = SpaceRock::from_state(name, calc_xyz_from_kep(*orbit), 2460500.5);
+            assert_eq!((&a.name, a.position, a.velocity), (&b.name, b.position, b.velocity));
+        }
+    }
+
+    #[test]
+    fn only_barycentric_rocks_propagate() {
+        let mut rocks = rocks();
+        rocks[7].origin = "Sun".to_string();
+        assert!(matches!(analytic_propagate(&mut rocks, 2460500.5), Err(SpaceRocksError::InvalidInput(message)) if message.starts_with("rock 7")));
+        assert!(matches!(from_kepler_orbits(&["one"], &[], 2460500.5), Err(SpaceRocksError::InvalidInput(_))));
+    }
+}
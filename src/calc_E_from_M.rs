@@ -64,4 +64,14 @@ pub fn calc_E_from_M(e: f64, M: f64) -> f64 {
       }
     }
     return E;
-}
\ No newline at end of file
+}
+
+// The true anomaly from the mean anomaly, for elliptic or hyperbolic orbits.
+pub fn calc_f_from_M(e: f64, M: f64) -> f64 {
+    let E = calc_E_from_M(e, M);
+    if e < 1.0 {
+        2.0 * ((1.0 + e).sqrt() * (E / 2.0).sin()).atan2((1.0 - e).sqrt() * (E / 2.0).cos())
+    } else {
+        2.0 * ((e + 1.0).sqrt() * (E / 2.0).tanh()).atan2((e - 1.0).sqrt())
+    }
+}
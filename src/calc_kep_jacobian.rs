@@ -0,0 +1,43 @@
+use crate::statevector::StateVector;
+use crate::keplerorbit::KeplerOrbit;
+use crate::calc_kep_from_xyz::calc_kep_from_xyz;
+use crate::calc_xyz_from_kep::calc_xyz_from_kep;
+use nalgebra::Matrix6;
+
+
+// Partial derivatives of (x, y, z, vx, vy, vz) with respect to (a, e, inc, arg, node, f).
+pub fn calc_xyz_jacobian(kep: KeplerOrbit) -> Matrix6<f64> {
+
+    let steps = [1e-8 * kep.a.abs(), 1e-8, 1e-8, 1e-8, 1e-8, 1e-8];
+
+    let mut jac = Matrix6::zeros();
+    for k in 0..6 {
+        let plus = calc_xyz_from_kep(perturb(kep, k, steps[k]));
+        let minus = calc_xyz_from_kep(perturb(kep, k, -steps[k]));
+        for i in 0..3 {
+            jac[(i, k)] = (plus.position[i] - minus.position[i]) / (2.0 * steps[k]);
+            jac[(i + 3, k)] = (plus.velocity[i] - minus.velocity[i]) / (2.0 * steps[k]);
+        }
+    }
+    jac
+}
+
+// Partial derivatives of (a, e, inc, arg, node, f) with respect to (x, y, z, vx, vy, vz).
+// None if the elements are singular at this state (circular or planar orbits).
+pub fn calc_kep_jacobian(state: StateVector) -> Option<Matrix6<f64>> {
+    let kep = calc_kep_from_xyz(state);
+    calc_xyz_jacobian(kep).try_inverse()
+}
+
+fn perturb(kep: KeplerOrbit, index: usize, step: f64) -> KeplerOrbit {
+    let mut kep = kep;
+    match index {
+        0 => kep.a += step,
+        1 => kep.e += step,
+        2 => kep.inc += step,
+        3 => kep.arg += step,
+        4 => kep.node += step,
+        _ => kep.f += step,
+    }
+    kep
+}
@@ -0,0 +1,25 @@
+use crate::constants::MU_BARY;
+use crate::statevector::StateVector;
+use crate::keplerorbit::KeplerOrbit;
+use nalgebra::{Rotation3, Vector3};
+
+
+pub fn calc_xyz_from_kep(kep: KeplerOrbit) -> StateVector {
+
+    let p = kep.a * (1.0 - kep.e * kep.e);
+    let r = p / (1.0 + kep.e * kep.f.cos());
+    let v = (MU_BARY / p).sqrt();
+
+    // position and velocity in the perifocal frame
+    let position = Vector3::new(r * kep.f.cos(), r * kep.f.sin(), 0.0);
+    let velocity = Vector3::new(-v * kep.f.sin(), v * (kep.e + kep.f.cos()), 0.0);
+
+    let rot = Rotation3::from_axis_angle(&Vector3::z_axis(), kep.node)
+            * Rotation3::from_axis_angle(&Vector3::x_axis(), kep.inc)
+            * Rotation3::from_axis_angle(&Vector3::z_axis(), kep.arg);
+
+    let position = rot * position;
+    let velocity = rot * velocity;
+
+    StateVector::new(position.x, position.y, position.z, velocity.x, velocity.y, velocity.z)
+}
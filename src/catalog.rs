@@ -0,0 +1,226 @@
+use crate::spacerock::SpaceRock;
+use crate::keplerorbit::KeplerOrbit;
+use crate::calc_xyz_from_kep::calc_xyz_from_kep;
+use crate::error::{Result, SpaceRocksError};
+
+use serde::{Deserialize, Serialize};
+
+use std::io::{Read, Write};
+
+// Flat rows for exchanging rocks as csv or json. Distances are in au, velocities in au/day and angles in degrees;
+// the elements are osculating, about the barycentric mass as everywhere else.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct StateRow {
+    pub name: String,
+    pub epoch: f64,
+    pub frame: String,
+    pub origin: String,
+    pub x: f64,
+    pub y: f64,
+    pub z: f64,
+    pub vx: f64,
+    pub vy: f64,
+    pub vz: f64,
+    pub h: Option<f64>,
+    pub g: Option<f64>,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct ElementRow {
+    pub name: String,
+    pub epoch: f64,
+    pub frame: String,
+    pub origin: String,
+    pub a: f64,
+    pub e: f64,
+    pub inc: f64,
+    pub arg: f64,
+    pub node: f64,
+    pub f: f64,
+    pub h: Option<f64>,
+    pub g: Option<f64>,
+}
+
+// Which representation to write.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Columns {
+    State,
+    Elements,
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum Row {
+    State(StateRow),
+    Elements(ElementRow),
+}
+
+impl StateRow {
+
+    pub fn from_rock(rock: &SpaceRock) -> Self {
+        StateRow {
+            name: rock.name.clone(),
+            epoch: rock.epoch,
+            frame: rock.frame.name(),
+            origin: rock.origin.clone(),
+            x: rock.position.x,
+            y: rock.position.y,
+            z: rock.position.z,
+            vx: rock.velocity.x,
+            vy: rock.velocity.y,
+            vz: rock.velocity.z,
+            h: rock.h,
+            g: rock.g,
+        }
+    }
+
+    pub fn to_rock(&self) -> Result<SpaceRock> {
+        let mut rock = SpaceRock::from_xyz(&self.name, self.x, self.y, self.z, self.vx, self.vy, self.vz, self.epoch);
+        rock.frame = self.frame.parse()?;
+        rock.origin = self.origin.clone();
+        rock.h = self.h;
+        rock.g = self.g;
+        Ok(rock)
+    }
+}
+
+impl ElementRow {
+
+    pub fn from_rock(rock: &SpaceRock) -> Self {
+        let orbit = rock.kepler_orbit();
+        ElementRow {
+            name: rock.name.clone(),
+            epoch: rock.epoch,
+            frame: rock.frame.name(),
+            origin: rock.origin.clone(),
+            a: orbit.a,
+            e: orbit.e,
+            inc: orbit.inc.to_degrees(),
+            arg: orbit.arg.to_degrees(),
+            node: orbit.node.to_degrees(),
+            f: orbit.f.to_degrees(),
+            h: rock.h,
+            g: rock.g,
+        }
+    }
+
+    pub fn to_rock(&self) -> Result<SpaceRock> {
+        let orbit = KeplerOrbit::new(self.a, self.e, self.inc.to_radians(), self.arg.to_radians(), self.node.to_radians(), self.f.to_radians());
+        let mut rock = SpaceRock::from_state(&self.name, calc_xyz_from_kep(orbit), self.epoch);
+        rock.frame = self.frame.parse()?;
+        rock.origin = self.origin.clone();
+        rock.h = self.h;
+        rock.g = self.g;
+        Ok(rock)
+    }
+}
+
+impl Row {
+
+    fn to_rock(&self) -> Result<SpaceRock> {
+        match self {
+            Row::State(row) => row.to_rock(),
+            Row::Elements(row) => row.to_rock(),
+        }
+    }
+}
+
+pub fn write_csv<W: Write>(writer: W, rocks: &[SpaceRock], columns: Columns) -> Result<()> {
+    let mut writer = csv::Writer::from_writer(writer);
+    for rock in rocks {
+        match columns {
+            Columns::State => writer.serialize(StateRow::from_rock(rock))?,
+            Columns::Elements => writer.serialize(ElementRow::from_rock(rock))?,
+        }
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+// Read rocks written by write_csv, or anything else with the same columns, taking the representation from the header.
+pub fn read_csv<R: Read>(reader: R) -> Result<Vec<SpaceRock>> {
+    let mut reader = csv::ReaderBuilder::new().trim(csv::Trim::All).from_reader(reader);
+    let headers = reader.headers()?.clone();
+    if headers.iter().any(|header| header == "x") {
+        reader.deserialize::<StateRow>().map(|row| row?.to_rock()).collect()
+    } else if headers.iter().any(|header| header == "a") {
+        reader.deserialize::<ElementRow>().map(|row| row?.to_rock()).collect()
+    } else {
+        Err(SpaceRocksError::Parse("csv has neither state (x, y, z, ...) nor element (a, e, inc, ...) columns".to_string()))
+    }
+}
+
+// An array of rows, one object per rock.
+pub fn write_json<W: Write>(writer: W, rocks: &[SpaceRock], columns: Columns) -> Result<()> {
+    match columns {
+        Columns::State => serde_json::to_writer_pretty(writer, &rocks.iter().map(StateRow::from_rock).collect::<Vec<_>>())?,
+        Columns::Elements => serde_json::to_writer_pretty(writer, &rocks.iter().map(ElementRow::from_rock).collect::<Vec<_>>())?,
+    }
+    Ok(())
+}
+
+// Each object may be either kind of row.
+pub fn read_json<R: Read>(reader: R) -> Result<Vec<SpaceRock>> {
+    let rows: Vec<Row> = serde_json::from_reader(reader)?;
+    rows.iter().map(Row::to_rock).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::frame::Frame;
+
+    use nalgebra::Matrix3;
+
+    fn rocks() -> Vec<SpaceRock> {
+        let mut first = SpaceRock::from_xyz("2004 MN4", 0.92, -0.31, 0.04, 0.006, 0.015, -0.0004, 2460000.5);
+        first.h = Some(19.09);
+        first.g = Some(0.24);
+        let mut second = SpaceRock::from_xyz("Arrokoth", -12.1, 40.3, 1.7, -0.0027, -0.0008, 0.0001, 2460000.5);
+        second.frame = Frame::EclipJ2000;
+        vec![first, second]
+    }
+
+    fn assert_same(read: &[SpaceRock], written: &[SpaceRock], tolerance: f64) {
+        assert_eq!(read.len(), written.len());
+        for (a, b) in read.iter().zip(written) {
+            assert_eq!((&a.name, a.epoch, &a.frame, &a.origin, a.h, a.g), (&b.name, b.epoch, &b.frame, &b.origin, b.h, b.g));
+            assert!((a.position - b.position).norm() <= tolerance * b.position.norm(), "{}", a.name);
+            assert!((a.velocity - b.velocity).norm() <= tolerance * b.velocity.norm(), "{}", a.name);
+        }
+    }
+
+    #[test]
+    fn csv_round_trips() {
+        for (columns, tolerance) in [(Columns::State, 0.0), (Columns::Elements, 1e-12)] {
+            let mut bytes = Vec::new();
+            write_csv(&mut bytes, &rocks(), columns).unwrap();
+            assert_same(&read_csv(bytes.as_slice()).unwrap(), &rocks(), tolerance);
+        }
+    }
+
+    #[test]
+    fn json_round_trips() {
+        for (columns, tolerance) in [(Columns::State, 0.0), (Columns::Elements, 1e-12)] {
+            let mut bytes = Vec::new();
+            write_json(&mut bytes, &rocks(), columns).unwrap();
+            assert_same(&read_json(bytes.as_slice()).unwrap(), &rocks(), tolerance);
+        }
+    }
+
+    #[test]
+    fn empty_magnitudes_read_as_none() {
+        let csv = "name,epoch,frame,origin,x,y,z,vx,vy,vz,h,g\nrock,2460000.5,J2000,SSB,1.0,0.0,0.0,0.0,0.017,0.0,,\n";
+        let rocks = read_csv(csv.as_bytes()).unwrap();
+        assert_eq!((rocks[0].h, rocks[0].g), (None, None));
+    }
+
+    #[test]
+    fn an_unregistered_frame_can_not_be_read_back() {
+        let mut rocks = rocks();
+        rocks[0].frame = Frame::Custom { name: "UNREGISTERED".to_string(), rotation: Matrix3::identity() };
+        let mut bytes = Vec::new();
+        write_csv(&mut bytes, &rocks, Columns::State).unwrap();
+        assert!(matches!(read_csv(bytes.as_slice()), Err(SpaceRocksError::UnknownFrame(name)) if name == "UNREGISTERED"));
+    }
+}
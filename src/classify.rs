@@ -0,0 +1,160 @@
+use crate::spacerock::SpaceRock;
+use crate::keplerorbit::KeplerOrbit;
+use crate::forces::ForceModel;
+use crate::integrate::Integrator;
+use crate::frame::Frame;
+use crate::error::{Result, SpaceRocksError};
+use crate::resonance::{ElementHistory, find_resonances};
+
+const A_JUPITER: f64 = 5.2026;
+const A_NEPTUNE: f64 = 30.07;
+const Q_COMET: f64 = 7.35;     // perihelion inside which low-Tisserand bodies count as comets (Gladman et al. 2008)
+const A_HALLEY: f64 = 34.2;    // a 200 year period
+const E_DETACHED: f64 = 0.24;
+const SCATTERING_DELTA_A: f64 = 1.5; // au of semimajor axis change over the integration
+const Q_SCATTERING: f64 = 35.0;      // stands in for the integration when there is none
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DynamicalClass {
+    Atira,
+    Aten,
+    Apollo,
+    Amor,
+    MarsCrosser,
+    MainBelt,
+    Hilda,
+    JupiterTrojan,
+    Centaur,
+    JupiterFamilyComet,
+    HalleyTypeComet,
+    LongPeriodComet,
+    Classical,
+    Resonant { p: u32, q: u32 }, // p:q with neptune, so the plutinos are 3:2
+    Scattering,
+    Detached,
+    Hyperbolic,
+    Unclassified,
+}
+
+impl DynamicalClass {
+
+    pub fn is_neo(&self) -> bool {
+        matches!(self, DynamicalClass::Atira | DynamicalClass::Aten | DynamicalClass::Apollo | DynamicalClass::Amor)
+    }
+
+    pub fn is_tno(&self) -> bool {
+        matches!(self, DynamicalClass::Classical | DynamicalClass::Resonant { .. } | DynamicalClass::Scattering | DynamicalClass::Detached)
+    }
+}
+
+// The Tisserand parameter with respect to a planet on a circular, ecliptic orbit.
+pub fn tisserand(orbit: &KeplerOrbit, a_planet: f64) -> f64 {
+    a_planet / orbit.a + 2.0 * orbit.inc.cos() * (orbit.a / a_planet * (1.0 - orbit.e * orbit.e)).sqrt()
+}
+
+// Classify a rock from its osculating heliocentric ecliptic elements alone, since the boundaries between the
+// inner classes are drawn in heliocentric distances. Without an integration there is no telling resonant
+// TNOs apart, so they fall in with the others, and scattering is judged by perihelion distance.
+pub fn classify(rock: &SpaceRock) -> Result<DynamicalClass> {
+    let orbit = rock.heliocentric_orbit()?;
+    Ok(match classify_orbit(&orbit) {
+        DynamicalClass::Classical | DynamicalClass::Detached if orbit.a * (1.0 - orbit.e) < Q_SCATTERING => DynamicalClass::Scattering,
+        class => class,
+    })
+}
+
+// Classify from element histories of the rock and neptune sampled at the same epochs, following Gladman et al. (2008)
+// for the outer solar system: resonant if any angle librates throughout, then scattering if the semimajor axis
+// wanders, then detached or classical by eccentricity. The other classes use the mean elements, in whatever
+// origin the history was recorded in.
+pub fn classify_history(rock: &ElementHistory, neptune: &ElementHistory) -> Result<DynamicalClass> {
+
+    let mean = rock.mean_elements()?;
+    let last = rock.orbits[rock.orbits.len() - 1];
+    let orbit = KeplerOrbit::new(mean.a, mean.e, mean.inc, last.arg, last.node, last.f);
+
+    let class = classify_orbit(&orbit);
+    if class != DynamicalClass::Centaur && !class.is_tno() {
+        return Ok(class);
+    }
+
+    if let Some(resonance) = find_resonances(rock, neptune, 5, 1)?.iter().find(|resonance| resonance.is_resonant()) {
+        return Ok(DynamicalClass::Resonant { p: resonance.angle.p, q: resonance.angle.q });
+    }
+    if class == DynamicalClass::Centaur {
+        return Ok(class);
+    }
+    if mean.a_max - mean.a_min > SCATTERING_DELTA_A {
+        return Ok(DynamicalClass::Scattering);
+    }
+    Ok(class)
+}
+
+// Integrate the rock through the epochs along with the bodies, and classify it from its history. Neptune has to be
+// among the bodies or the force model's perturbers, and the rocks should be barycentric.
+pub fn classify_with_integration(rock: &SpaceRock, bodies: &[SpaceRock], forces: &ForceModel, integrator: &Integrator, epochs: &[f64]) -> Result<DynamicalClass> {
+
+    let mut rocks = bodies.to_vec();
+    rocks.push(rock.clone());
+    let histories = ElementHistory::record(&mut rocks, forces, integrator, epochs, &Frame::EclipJ2000)?;
+
+    let neptune = histories.iter().find(|history| history.name.to_lowercase().starts_with("neptune"))
+        .ok_or_else(|| SpaceRocksError::MissingBody("neptune".to_string()))?;
+    match histories.iter().find(|history| history.name == rock.name) {
+        Some(history) => classify_history(history, neptune),
+        None => Ok(DynamicalClass::Unclassified),
+    }
+}
+
+fn classify_orbit(orbit: &KeplerOrbit) -> DynamicalClass {
+
+    let (a, e) = (orbit.a, orbit.e);
+    if e >= 1.0 || a <= 0.0 {
+        return DynamicalClass::Hyperbolic;
+    }
+    let q = a * (1.0 - e);
+    let big_q = a * (1.0 + e);
+    let t_jupiter = tisserand(orbit, A_JUPITER);
+
+    // nearly isotropic comets (Levison 1996), whatever their perihelion
+    if t_jupiter < 2.0 && q < Q_COMET {
+        return if a < A_HALLEY { DynamicalClass::HalleyTypeComet } else { DynamicalClass::LongPeriodComet };
+    }
+
+    if q < 1.3 {
+        return if big_q < 0.983 {
+            DynamicalClass::Atira
+        } else if a < 1.0 {
+            DynamicalClass::Aten
+        } else if q < 1.017 {
+            DynamicalClass::Apollo
+        } else {
+            DynamicalClass::Amor
+        };
+    }
+
+    // the hildas and trojans are held by jupiter's resonances, whatever their tisserand parameter
+    if (3.7..4.2).contains(&a) && e < 0.3 {
+        return DynamicalClass::Hilda;
+    }
+    if (5.05..5.35).contains(&a) && e < 0.3 {
+        return DynamicalClass::JupiterTrojan;
+    }
+
+    if q < Q_COMET && t_jupiter < 3.0 {
+        return DynamicalClass::JupiterFamilyComet;
+    }
+
+    if a < 3.7 {
+        return if q < 1.666 { DynamicalClass::MarsCrosser } else { DynamicalClass::MainBelt };
+    }
+    if a < A_NEPTUNE {
+        return if q >= Q_COMET { DynamicalClass::Centaur } else { DynamicalClass::Unclassified };
+    }
+
+    if e > E_DETACHED {
+        DynamicalClass::Detached
+    } else {
+        DynamicalClass::Classical
+    }
+}
@@ -15,6 +15,25 @@ pub const DEG_TO_RAD: f64 = std::f64::consts::PI / 180.0;
 pub const MU_BARY: f64 = 0.00029630927493457475;
 pub const SPEED_OF_LIGHT: f64 = 173.14463268466926; // speed of light in au/day
 
+// solar radiation pressure at 1 au, in au/day^2 per unit area-to-mass ratio (m^2/kg)
+pub const SOLAR_PRESSURE_1AU: f64 = 1361.0 / 299_792_458.0 * M_TO_AU * SECONDS_PER_DAY * SECONDS_PER_DAY;
+
+// GM values in au^3/day^2 (DE440)
+pub const GM_SUN: f64 = 0.00029591220828411956;
+pub const GM_MERCURY: f64 = 4.912500194800129e-11;
+pub const GM_VENUS: f64 = 7.243452332644119e-10;
+pub const GM_EARTH: f64 = 8.8876924467066e-10;
+pub const GM_MOON: f64 = 1.0931894623004141e-11;
+pub const GM_MARS: f64 = 9.549548829780194e-11;
+pub const GM_JUPITER: f64 = 2.8253458252257923e-07;
+pub const GM_SATURN: f64 = 8.45970599337629e-08;
+pub const GM_URANUS: f64 = 1.2920265649682404e-08;
+pub const GM_NEPTUNE: f64 = 1.524357347885105e-08;
+pub const GM_PLUTO: f64 = 2.175096464893358e-12;
+pub const GM_CERES: f64 = 1.3964516195615619e-13;
+pub const GM_VESTA: f64 = 3.854802725136881e-14;
+pub const GM_PALLAS: f64 = 3.047114600457603e-14;
+
 pub const ROTATION_J2000: Matrix3<f64> = Matrix3::new(1.0, 0.0, 0.0,
                                                       0.0, 1.0, 0.0,
                                                       0.0, 0.0, 1.0);
@@ -31,19 +50,36 @@ pub const ROTATION_ECLIPJ2000: Matrix3<f64> = Matrix3::new(1.0, 0.0, 0.0,
                                                            0.0, 0.91748206206918181, 0.39777715593191371,
                                                            0.0, -0.39777715593191371, 0.91748206206918181);
 
-pub const ROTATION_INVARIABLE: Matrix3<f64> = Matrix3::new(-0.3023595432982142,  0.8743824933349968,  0.3795182677654754,
-                                                           -0.9527924404431956, -0.2888105590660314, -0.0936832539501452,
-                                                            0.0263975536104876, -0.389928162416098 ,  0.920429658444365);
+// the invariable plane of Souami & Souchay (2012), inclined 1°34'43.3" to the ecliptic with its node at 107°34'56",
+// taking the x axis along the node
+pub const ROTATION_INVARIABLE: Matrix3<f64> = Matrix3::new(-0.30207411947905916,  0.8746213750946014,  0.3791947739204482,
+                                                           -0.9529226020034154, -0.28800112143132334, -0.09483600922297714,
+                                                            0.026262919334901647, -0.38999077461130904, 0.9204441616883012);
+
+// the massive bodies of the DE440 / SB441-N16 kernels, for use as ephemeris perturbers
+pub const PERTURBERS: [&str; 14] = ["Sun", "Mercury Barycenter", "Venus Barycenter", "Earth", "Moon",
+                                    "Mars Barycenter", "Jupiter Barycenter", "Saturn Barycenter", "Uranus Barycenter",
+                                    "Neptune Barycenter", "Pluto Barycenter", "Ceres", "Vesta", "Pallas"];
 
-// make a hash map of the rotation matrices
+// make a hash map of the masses of the bodies known to spice, keyed by lowercase name
 lazy_static! {
-    pub static ref ROTATION_MATRICES: HashMap<String, Matrix3<f64>> = {
+    pub static ref MASSES: HashMap<String, f64> = {
         let mut m = HashMap::new();
-        m.insert("J2000".to_string(), ROTATION_J2000);
-        m.insert("FK4".to_string(), ROTATION_FK4);
-        m.insert("GALACTIC".to_string(), ROTATION_GALACTIC);
-        m.insert("ECLIPJ2000".to_string(), ROTATION_ECLIPJ2000);
-        m.insert("INVARIABLE".to_string(), ROTATION_INVARIABLE);
+        m.insert("sun".to_string(), GM_SUN);
+        m.insert("mercury barycenter".to_string(), GM_MERCURY);
+        m.insert("venus barycenter".to_string(), GM_VENUS);
+        m.insert("earth".to_string(), GM_EARTH);
+        m.insert("moon".to_string(), GM_MOON);
+        m.insert("earth barycenter".to_string(), GM_EARTH + GM_MOON);
+        m.insert("mars barycenter".to_string(), GM_MARS);
+        m.insert("jupiter barycenter".to_string(), GM_JUPITER);
+        m.insert("saturn barycenter".to_string(), GM_SATURN);
+        m.insert("uranus barycenter".to_string(), GM_URANUS);
+        m.insert("neptune barycenter".to_string(), GM_NEPTUNE);
+        m.insert("pluto barycenter".to_string(), GM_PLUTO);
+        m.insert("ceres".to_string(), GM_CERES);
+        m.insert("vesta".to_string(), GM_VESTA);
+        m.insert("pallas".to_string(), GM_PALLAS);
         m
     };
-}
\ No newline at end of file
+}
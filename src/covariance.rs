@@ -0,0 +1,33 @@
+use nalgebra::{Matrix2, Matrix6};
+
+use std::f64::consts::PI;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Covariance {
+    Cartesian(Matrix6<f64>), // x, y, z, vx, vy, vz
+    Keplerian(Matrix6<f64>), // a, e, inc, arg, node, f
+}
+
+// Uncertainty ellipse on the sky, in radians. The position angle of the major axis is measured from north through east.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ErrorEllipse {
+    pub semi_major: f64,
+    pub semi_minor: f64,
+    pub position_angle: f64,
+}
+
+impl ErrorEllipse {
+
+    // From the covariance of (ra * cos(dec), dec).
+    pub fn from_covariance(covariance: &Matrix2<f64>) -> Self {
+        let eigen = covariance.symmetric_eigen();
+        let (major, minor) = if eigen.eigenvalues[0] >= eigen.eigenvalues[1] { (0, 1) } else { (1, 0) };
+        let axis = eigen.eigenvectors.column(major);
+        ErrorEllipse {
+            semi_major: eigen.eigenvalues[major].max(0.0).sqrt(),
+            semi_minor: eigen.eigenvalues[minor].max(0.0).sqrt(),
+            position_angle: axis[0].atan2(axis[1]).rem_euclid(PI),
+        }
+    }
+}
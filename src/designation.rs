@@ -0,0 +1,344 @@
+use crate::error::{Result, SpaceRocksError};
+
+// Minor planet, comet and natural satellite designations in the MPC's packed forms, e.g.
+//   1 <-> 00001                     100000 <-> A0000              620000 <-> ~0000
+//   2007 TA418 <-> K07Tf8A          2024 AB631 <-> _OA004S        2040 P-L <-> PLS2040
+//   1P <-> 0001P                    C/1995 O1 <-> CJ95O010        P/1930 J1-B <-> PJ30J01b
+//   P/2016 BA14 <-> PK16B14A        S/2019 J 1 <-> SK19J010       Jupiter XIII <-> J013S
+
+const BASE62: &[u8] = b"0123456789ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz";
+const COMET_TYPES: &str = "PCDXAI";
+const PLANETS: [(char, &str); 5] = [('M', "Mars"), ('J', "Jupiter"), ('S', "Saturn"), ('U', "Uranus"), ('N', "Neptune")];
+const SURVEYS: [(&str, &str); 4] = [("PLS", "P-L"), ("T1S", "T-1"), ("T2S", "T-2"), ("T3S", "T-3")];
+
+fn invalid(name: &str) -> SpaceRocksError {
+    SpaceRocksError::Parse(format!("'{}' is not a designation", name))
+}
+
+fn to_base62(value: u32) -> char {
+    BASE62[value as usize] as char
+}
+
+fn from_base62(c: char) -> Option<u32> {
+    BASE62.iter().position(|&b| b as char == c).map(|value| value as u32)
+}
+
+fn digits(s: &str) -> Option<u32> {
+    if s.is_empty() || !s.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    s.parse().ok()
+}
+
+// Letters of a provisional designation skip I, so there are 24 half-months and 25 second letters.
+fn letter_index(c: char) -> Option<u32> {
+    match c {
+        'A'..='H' => Some(c as u32 - 'A' as u32),
+        'J'..='Z' => Some(c as u32 - 'A' as u32 - 1),
+        _ => None,
+    }
+}
+
+fn letter(index: u32) -> char {
+    char::from_u32('A' as u32 + index + if index >= 8 { 1 } else { 0 }).unwrap()
+}
+
+fn pack_year(year: u32) -> Option<String> {
+    match year {
+        1800..=2099 => Some(format!("{}{:02}", to_base62(year / 100), year % 100)),
+        _ => None,
+    }
+}
+
+fn unpack_year(packed: &str) -> Option<u32> {
+    let mut chars = packed.chars();
+    let century = chars.next().and_then(from_base62).filter(|century| (18..=20).contains(century))?;
+    Some(100 * century + digits(chars.as_str())?)
+}
+
+// The order within a half-month, as two characters: 00-99, then A0-z9 up to 619.
+fn pack_order(order: u32) -> Option<String> {
+    match order {
+        0..=99 => Some(format!("{:02}", order)),
+        100..=619 => Some(format!("{}{}", to_base62(order / 10), order % 10)),
+        _ => None,
+    }
+}
+
+fn unpack_order(packed: &str) -> Option<u32> {
+    let mut chars = packed.chars();
+    let (tens, ones) = (from_base62(chars.next()?)?, chars.next()?.to_digit(10)?);
+    Some(10 * tens + ones)
+}
+
+fn to_roman(mut value: u32) -> String {
+    let numerals = [(100, "C"), (90, "XC"), (50, "L"), (40, "XL"), (10, "X"), (9, "IX"), (5, "V"), (4, "IV"), (1, "I")];
+    let mut roman = String::new();
+    for (size, numeral) in numerals {
+        while value >= size {
+            roman.push_str(numeral);
+            value -= size;
+        }
+    }
+    roman
+}
+
+fn from_roman(roman: &str) -> Option<u32> {
+    let value = roman.chars().rev().try_fold((0u32, 0), |(total, largest), c| {
+        let digit = match c { 'I' => 1, 'V' => 5, 'X' => 10, 'L' => 50, 'C' => 100, _ => return None };
+        Some(if digit < largest { (total.checked_sub(digit)?, largest) } else { (total + digit, digit) })
+    })?.0;
+    // only accept the canonical spelling
+    (value > 0 && to_roman(value) == roman).then_some(value)
+}
+
+// A year, half-month letter, second letter and order, e.g. (2007, 'T', 'A', 418).
+fn pack_provisional(year: u32, half_month: char, second: char, order: u32) -> Option<String> {
+    letter_index(half_month).filter(|&index| index < 24)?;
+    let index = letter_index(second)?;
+    if order < 620 {
+        return Some(format!("{}{}{}{}", pack_year(year)?, half_month, pack_order(order)?, second));
+    }
+    // the extended form, for cycles beyond the two-character order
+    let value = (order - 620).checked_mul(25)?.checked_add(index)?;
+    if !(2000..2062).contains(&year) || value >= 62u32.pow(4) {
+        return None;
+    }
+    let encoded: String = (0..4).rev().map(|k| to_base62(value / 62u32.pow(k) % 62)).collect();
+    Some(format!("_{}{}{}", to_base62(year - 2000), half_month, encoded))
+}
+
+fn unpack_provisional(packed: &str) -> Option<String> {
+    let chars: Vec<char> = packed.chars().collect();
+    let (year, half_month, second, order) = match chars[..] {
+        ['_', year, half_month, ..] if chars.len() == 7 => {
+            let value = chars[3..].iter().try_fold(0, |value, &c| Some(62 * value + from_base62(c)?))?;
+            (2000 + from_base62(year)?, half_month, letter(value % 25), 620 + value / 25)
+        }
+        [_, _, _, half_month, _, _, second] => {
+            (unpack_year(&packed[..3])?, half_month, second, unpack_order(&packed[4..6])?)
+        }
+        _ => return None,
+    };
+    letter_index(half_month).filter(|&index| index < 24)?;
+    letter_index(second)?;
+    Some(match order {
+        0 => format!("{} {}{}", year, half_month, second),
+        _ => format!("{} {}{}{}", year, half_month, second, order),
+    })
+}
+
+// "2007 TA418" as its parts.
+fn parse_provisional(designation: &str) -> Option<(u32, char, char, u32)> {
+    let (year, rest) = designation.split_once(' ')?;
+    let year = match year.strip_prefix('A') {
+        Some(year) if year.len() == 3 => 1000 + digits(year)?,
+        _ if year.len() == 4 => digits(year)?,
+        _ => return None,
+    };
+    let mut chars = rest.chars();
+    let (half_month, second) = (chars.next()?, chars.next()?);
+    let order = match chars.as_str() {
+        "" => 0,
+        order => digits(order)?,
+    };
+    Some((year, half_month, second, order))
+}
+
+// A comet's provisional designation after the type, e.g. "1995 O1" or "1930 J1-B", as year, half-month,
+// order and fragment.
+fn parse_comet(designation: &str) -> Option<(u32, char, u32, Option<char>)> {
+    let (designation, fragment) = match designation.split_once('-') {
+        Some((designation, fragment)) if fragment.len() == 1 => (designation, fragment.chars().next().filter(char::is_ascii_uppercase)),
+        Some(_) => return None,
+        None => (designation, None),
+    };
+    let (year, rest) = designation.split_once(' ')?;
+    let mut chars = rest.chars();
+    let half_month = chars.next()?;
+    Some((digits(year)?, half_month, digits(chars.as_str())?, fragment))
+}
+
+pub fn pack(designation: &str) -> Result<String> {
+    let name = designation.split_whitespace().collect::<Vec<_>>().join(" ");
+    packed(&name).ok_or_else(|| invalid(designation))
+}
+
+fn packed(name: &str) -> Option<String> {
+    match name.as_bytes() {
+        [b'(', ..] => name[1..].split_once(')').and_then(|(inner, _)| digits(inner).map_or_else(|| packed(inner), pack_number)),
+        [b'0'..=b'9', ..] => {
+            let (first, rest) = name.split_once(' ').unwrap_or((name, ""));
+            if let Some((packed, _)) = SURVEYS.iter().find(|(_, survey)| *survey == rest) {
+                digits(first).map(|number| format!("{}{}", packed, number)).filter(|packed| packed.len() == 7)
+            } else if let Some((year, half_month, second, order)) = parse_provisional(name) {
+                pack_provisional(year, half_month, second, order)
+            } else if let Some(packed) = pack_numbered_comet(first).filter(|_| rest.is_empty()) {
+                Some(packed)
+            } else {
+                // a number, perhaps followed by a name as in "1 Ceres (A801 AA)"
+                digits(first).and_then(pack_number)
+            }
+        }
+        // provisional designations from before 1925, e.g. "A801 AA"
+        [b'A', b'0'..=b'9', ..] => parse_provisional(name).and_then(|(year, half_month, second, order)| pack_provisional(year, half_month, second, order)),
+        [b'S', b'/', ..] => {
+            let parts: Vec<&str> = name[2..].split(' ').collect();
+            match parts[..] {
+                [year, planet, order] if PLANETS.iter().any(|(code, _)| planet == code.to_string()) => {
+                    Some(format!("S{}{}{}0", pack_year(digits(year)?)?, planet, pack_order(digits(order)?)?))
+                }
+                _ => None,
+            }
+        }
+        [kind, b'/', ..] if COMET_TYPES.as_bytes().contains(kind) => {
+            // drop a trailing name, as in "C/1995 O1 (Hale-Bopp)"
+            let designation = name[2..].split(" (").next().unwrap_or("");
+            let kind = *kind as char;
+            if let Some((year, half_month, second, order)) = parse_provisional(designation).filter(|(_, _, second, _)| second.is_ascii_uppercase()) {
+                pack_provisional(year, half_month, second, order).map(|packed| format!("{}{}", kind, packed))
+            } else {
+                parse_comet(designation).and_then(|(year, half_month, order, fragment)| {
+                    letter_index(half_month).filter(|&index| index < 24)?;
+                    let fragment = fragment.map_or('0', |fragment| fragment.to_ascii_lowercase());
+                    Some(format!("{}{}{}{}{}", kind, pack_year(year)?, half_month, pack_order(order)?, fragment))
+                })
+            }
+        }
+        _ => {
+            let (planet, numeral) = name.split_once(' ').unwrap_or((name, ""));
+            PLANETS.iter().find(|(_, name)| *name == planet)
+                .and_then(|(code, _)| Some(format!("{}{:03}S", code, from_roman(numeral).filter(|&number| number < 1000)?)))
+        }
+    }
+}
+
+// "1P", or with its name as in "1P/Halley".
+fn pack_numbered_comet(designation: &str) -> Option<String> {
+    let designation = designation.split('/').next()?;
+    let kind = designation.chars().last().filter(|kind| COMET_TYPES.contains(*kind))?;
+    let number = digits(&designation[..designation.len() - 1]).filter(|number| (1..10000).contains(number))?;
+    Some(format!("{:04}{}", number, kind))
+}
+
+fn pack_number(number: u32) -> Option<String> {
+    match number {
+        1..=99999 => Some(format!("{:05}", number)),
+        100000..=619999 => Some(format!("{}{:04}", to_base62(number / 10000), number % 10000)),
+        620000..=15396335 => {
+            let value = number - 620000;
+            Some(format!("~{}", (0..4).rev().map(|k| to_base62(value / 62u32.pow(k) % 62)).collect::<String>()))
+        }
+        _ => None,
+    }
+}
+
+pub fn unpack(packed: &str) -> Result<String> {
+    let trimmed = packed.trim();
+    if !trimmed.is_ascii() {
+        return Err(invalid(packed));
+    }
+    let chars: Vec<char> = trimmed.chars().collect();
+    let unpacked = match chars[..] {
+        [_, _, _, _, _] if digits(trimmed).is_some() => digits(trimmed).filter(|&number| number > 0).map(|number| number.to_string()),
+        ['~', ..] if chars.len() == 5 => {
+            chars[1..].iter().try_fold(0, |value, &c| Some(62 * value + from_base62(c)?)).map(|value| (620000 + value).to_string())
+        }
+        [first, _, _, _, _] if first.is_ascii_alphabetic() && digits(&trimmed[1..]).is_some() => {
+            Some((from_base62(first).unwrap() * 10000 + digits(&trimmed[1..]).unwrap()).to_string())
+        }
+        [_, _, _, _, kind] if COMET_TYPES.contains(kind) => {
+            digits(&trimmed[..4]).filter(|&number| number > 0).map(|number| format!("{}{}", number, kind))
+        }
+        [planet, _, _, _, 'S'] => {
+            let name = PLANETS.iter().find(|(code, _)| *code == planet).map(|(_, name)| name);
+            name.and_then(|name| Some(format!("{} {}", name, to_roman(digits(&trimmed[1..4]).filter(|&number| number > 0)?))))
+        }
+        [_, _, _, _, _, _, _] => match SURVEYS.iter().find(|(code, _)| trimmed.starts_with(code)) {
+            Some((_, survey)) => digits(&trimmed[3..]).map(|number| format!("{} {}", number, survey)),
+            None => unpack_provisional(trimmed),
+        },
+        ['S', _, _, _, planet, _, _, '0'] if PLANETS.iter().any(|(code, _)| *code == planet) => {
+            unpack_year(&trimmed[1..4]).zip(unpack_order(&trimmed[5..7]))
+                .map(|(year, order)| format!("S/{} {} {}", year, planet, order))
+        }
+        [kind, _, _, _, half_month, _, _, last] if COMET_TYPES.contains(kind) => {
+            if last.is_ascii_uppercase() {
+                unpack_provisional(&trimmed[1..]).map(|designation| format!("{}/{}", kind, designation))
+            } else {
+                let fragment = match last {
+                    '0' => String::new(),
+                    'a'..='z' => format!("-{}", last.to_ascii_uppercase()),
+                    _ => return Err(invalid(packed)),
+                };
+                letter_index(half_month).filter(|&index| index < 24)
+                    .and(unpack_year(&trimmed[1..4]).zip(unpack_order(&trimmed[5..7])))
+                    .filter(|&(_, order)| order > 0)
+                    .map(|(year, order)| format!("{}/{} {}{}{}", kind, year, half_month, order, fragment))
+            }
+        }
+        _ => None,
+    };
+    unpacked.ok_or_else(|| invalid(packed))
+}
+
+pub fn is_packed(name: &str) -> bool {
+    unpack(name).is_ok()
+}
+
+pub fn is_designation(name: &str) -> bool {
+    is_packed(name) || pack(name).is_ok()
+}
+
+// The canonical unpacked form of a designation given either way, with the spacing tidied and any name
+// dropped, so "(1) Ceres", "1 Ceres (A801 AA)" and "00001" all become "1".
+pub fn normalise(name: &str) -> Result<String> {
+    unpack(name).or_else(|_| unpack(&pack(name)?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const EXAMPLES: [(&str, &str); 14] = [
+        ("1", "00001"),
+        ("100000", "A0000"),
+        ("620000", "~0000"),
+        ("3140113", "~AZaz"),
+        ("2007 TA418", "K07Tf8A"),
+        ("1998 SQ108", "J98SA8Q"),
+        ("2024 AB631", "_OA004S"),
+        ("2040 P-L", "PLS2040"),
+        ("1P", "0001P"),
+        ("C/1995 O1", "CJ95O010"),
+        ("P/1930 J1-B", "PJ30J01b"),
+        ("P/2016 BA14", "PK16B14A"),
+        ("S/2019 J 1", "SK19J010"),
+        ("Jupiter XIII", "J013S"),
+    ];
+
+    #[test]
+    fn examples_round_trip() {
+        for (unpacked, packed) in EXAMPLES {
+            assert_eq!(pack(unpacked).unwrap(), packed, "packing {}", unpacked);
+            assert_eq!(unpack(packed).unwrap(), unpacked, "unpacking {}", packed);
+        }
+    }
+
+    #[test]
+    fn names_normalise_to_the_designation() {
+        for name in ["(1) Ceres", "1 Ceres (A801 AA)", "00001"] {
+            assert_eq!(normalise(name).unwrap(), "1");
+        }
+        assert_eq!(normalise("C/1995 O1 (Hale-Bopp)").unwrap(), "C/1995 O1");
+    }
+
+    #[test]
+    fn out_of_range_designations_are_rejected() {
+        assert!(pack("2024 AB4000000000").is_err());
+        assert!(pack("2062 AB631").is_err());
+        assert!(pack("15396336").is_err());
+        assert!(pack("Jupiter IIIIIIIIIIIX").is_err());
+        assert!(unpack("K07Tf8").is_err());
+    }
+}
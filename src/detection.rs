@@ -1,8 +1,11 @@
 use nalgebra::Vector3;
 use crate::spacerock::SpaceRock;
+use crate::designation;
+use crate::error::Result;
 
 #[allow(dead_code)]
 //#[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Detection {
     pub ra: f64,
     pub dec: f64,
@@ -33,6 +36,12 @@ impl Detection {
         }
     }
 
+    // As SpaceRock::normalise_name, so detections match rocks however their designations were written.
+    pub fn normalise_objid(&mut self) -> Result<()> {
+        self.objid = designation::normalise(&self.objid)?;
+        Ok(())
+    }
+
     // pub fn generate_orbit(&self, r: f64, r_rate: f64) -> SpaceRock {
     //     let rho = self.calculate_rho(r);
     //     let rho_rate = self.calculate_rho_rate(r, r_rate);
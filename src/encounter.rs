@@ -0,0 +1,284 @@
+use crate::spacerock::SpaceRock;
+use crate::constants::GM_SUN;
+
+use nalgebra::Vector3;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum EncounterRadius {
+    Hill(f64),  // a multiple of each body's Hill radius
+    Fixed(f64), // a fixed distance in au
+}
+
+// Target-plane coordinates of a hyperbolic encounter, in au. The zeta axis points opposite to
+// the projection of the body's heliocentric velocity onto the plane, following Valsecchi et al. (2003).
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct BPlane {
+    pub b: f64,
+    pub xi: f64,
+    pub zeta: f64,
+    pub v_infinity: f64, // au/day
+}
+
+impl BPlane {
+
+    // From the rock's position and velocity relative to a body of mass gm, and the body's heliocentric velocity.
+    // None if the rock is bound to the body.
+    pub fn from_state(gm: f64, position: &Vector3<f64>, velocity: &Vector3<f64>, body_velocity: &Vector3<f64>) -> Option<Self> {
+
+        let r = position.norm();
+        let v_infinity_sq = velocity.norm_squared() - 2.0 * gm / r;
+        if v_infinity_sq <= 0.0 {
+            return None;
+        }
+        let v_infinity = v_infinity_sq.sqrt();
+
+        let h = position.cross(velocity);
+        let evec = velocity.cross(&h) / gm - position / r;
+        let e = evec.norm();
+        let e_hat = evec / e;
+        let h_hat = h.normalize();
+
+        // incoming asymptote
+        let s_hat = e_hat / e + (1.0 - 1.0 / (e * e)).sqrt() * h_hat.cross(&e_hat);
+        let b_vec = h.norm() / v_infinity * s_hat.cross(&h_hat);
+
+        let projected = body_velocity - body_velocity.dot(&s_hat) * s_hat;
+        let zeta_hat = -projected.normalize();
+        let xi_hat = s_hat.cross(&zeta_hat);
+
+        Some(BPlane {
+            b: b_vec.norm(),
+            xi: b_vec.dot(&xi_hat),
+            zeta: b_vec.dot(&zeta_hat),
+            v_infinity,
+        })
+    }
+}
+
+// A closest approach inside the encounter radius, with the epochs at which the rock crossed into and out of
+// that radius around it. The entry is None if the rock was already inside when the integration started and
+// the exit None if it was still inside at the end. Passages through the radius without a closest approach
+// inside it, as when a rock starts inside and recedes, aren't recorded.
+#[derive(Clone, Debug, PartialEq)]
+pub struct CloseEncounter {
+    pub rock: String,
+    pub body: String,
+    pub epoch: f64,             // time of closest approach
+    pub distance: f64,          // minimum distance in au
+    pub relative_velocity: f64, // au/day at closest approach
+    pub bplane: Option<BPlane>,
+    pub entry: Option<f64>,
+    pub exit: Option<f64>,
+}
+
+struct Body {
+    name: String,
+    gm: f64,
+    position: Vector3<f64>,
+    velocity: Vector3<f64>,
+}
+
+struct Snapshot {
+    epoch: f64,
+    positions: Vec<Vector3<f64>>,
+    velocities: Vec<Vector3<f64>>,
+    bodies: Vec<Body>,
+    sun_position: Vector3<f64>,
+    sun_velocity: Vector3<f64>,
+}
+
+// Where a rock stands against the radius around one body.
+#[derive(Clone, Default)]
+struct Passage {
+    entry: Option<f64>,
+    inside: bool,
+    open: Vec<usize>, // encounters recorded since the entry, waiting for the exit
+}
+
+// Watches every rock against every massive body other than the sun (integrated or read from the
+// ephemeris) at the end of each integration step, recording closest approaches inside the radius
+// along with the crossings into and out of it.
+pub(crate) struct EncounterTracker {
+    radius: EncounterRadius,
+    previous: Option<Snapshot>,
+    passages: Vec<Passage>, // for each rock and body
+    pub(crate) encounters: Vec<CloseEncounter>,
+}
+
+impl EncounterTracker {
+
+    pub(crate) fn new(radius: EncounterRadius) -> Self {
+        EncounterTracker {
+            radius,
+            previous: None,
+            passages: Vec::new(),
+            encounters: Vec::new(),
+        }
+    }
+
+    // Called at the start and after every step, with the perturbers' states the step ended on.
+    pub(crate) fn update(&mut self, rocks: &[SpaceRock], perturbers: &[SpaceRock], epoch: f64, positions: &[Vector3<f64>], velocities: &[Vector3<f64>]) {
+
+        let mut bodies = Vec::new();
+        let mut sun = None;
+        for (i, rock) in rocks.iter().enumerate() {
+            if rock.name.eq_ignore_ascii_case("sun") {
+                sun = Some((positions[i], velocities[i]));
+            } else if let Some(gm) = rock.mass {
+                bodies.push(Body { name: rock.name.clone(), gm, position: positions[i], velocity: velocities[i] });
+            }
+        }
+        for body in perturbers {
+            if body.name.eq_ignore_ascii_case("sun") {
+                sun = Some((body.position, body.velocity));
+            } else if let Some(gm) = body.mass {
+                bodies.push(Body { name: body.name.clone(), gm, position: body.position, velocity: body.velocity });
+            }
+        }
+        let (sun_position, sun_velocity) = sun.unwrap_or((Vector3::zeros(), Vector3::zeros()));
+
+        let current = Snapshot {
+            epoch,
+            positions: positions.to_vec(),
+            velocities: velocities.to_vec(),
+            bodies,
+            sun_position,
+            sun_velocity,
+        };
+
+        match self.previous.take() {
+            Some(previous) => self.check(rocks, &previous, &current),
+            None => self.start(rocks, &current),
+        }
+        self.previous = Some(current);
+    }
+
+    fn threshold(&self, body: &Body, sun_position: &Vector3<f64>) -> f64 {
+        match self.radius {
+            EncounterRadius::Fixed(radius) => radius,
+            EncounterRadius::Hill(factor) => {
+                let d_sun = (body.position - sun_position).norm();
+                factor * d_sun * (body.gm / (3.0 * GM_SUN)).cbrt()
+            }
+        }
+    }
+
+    // Note which rocks begin inside the radius of a body, with no known entry.
+    fn start(&mut self, rocks: &[SpaceRock], current: &Snapshot) {
+        let n = current.bodies.len();
+        self.passages = vec![Passage::default(); rocks.len() * n];
+        for i in 0..rocks.len() {
+            for (k, body) in current.bodies.iter().enumerate() {
+                let distance = (current.positions[i] - body.position).norm();
+                self.passages[i * n + k].inside = distance < self.threshold(body, &current.sun_position);
+            }
+        }
+    }
+
+    fn check(&mut self, rocks: &[SpaceRock], previous: &Snapshot, current: &Snapshot) {
+
+        let dt = current.epoch - previous.epoch;
+        let n = current.bodies.len();
+
+        for (i, rock) in rocks.iter().enumerate() {
+            for (k, (b0, b1)) in previous.bodies.iter().zip(&current.bodies).enumerate() {
+
+                if b1.name == rock.name || rock.name.eq_ignore_ascii_case("sun") {
+                    continue;
+                }
+
+                let r0 = previous.positions[i] - b0.position;
+                let v0 = previous.velocities[i] - b0.velocity;
+                let r1 = current.positions[i] - b1.position;
+                let v1 = current.velocities[i] - b1.velocity;
+                let threshold = self.threshold(b1, &current.sun_position);
+
+                // closest approach happens where the range rate changes from negative to positive
+                let closest = (r0.dot(&v0) * dt.signum() < 0.0 && r1.dot(&v1) * dt.signum() >= 0.0).then(|| {
+                    let tau = find_closest_approach(&r0, &v0, &r1, &v1, dt);
+                    let (r, v) = hermite(&r0, &v0, &r1, &v1, dt, tau);
+                    (tau, r, v)
+                });
+                let dips = closest.as_ref().is_some_and(|(_, r, _)| r.norm() < threshold);
+                let inside = r1.norm() < threshold;
+
+                let distance = |tau: f64| hermite(&r0, &v0, &r1, &v1, dt, tau).0.norm();
+                let passage = &mut self.passages[i * n + k];
+                let approach_end = closest.as_ref().map_or(1.0, |(tau, _, _)| *tau);
+                if !passage.inside && (inside || dips) {
+                    let tau = find_crossing(distance, threshold, 0.0, approach_end);
+                    passage.entry = Some(previous.epoch + tau * dt);
+                }
+
+                if let Some((tau, r, v)) = closest.filter(|_| dips) {
+                    let body_velocity = b1.velocity - current.sun_velocity;
+                    passage.open.push(self.encounters.len());
+                    self.encounters.push(CloseEncounter {
+                        rock: rock.name.clone(),
+                        body: b1.name.clone(),
+                        epoch: previous.epoch + tau * dt,
+                        distance: r.norm(),
+                        relative_velocity: v.norm(),
+                        bplane: BPlane::from_state(b1.gm, &r, &v, &body_velocity),
+                        entry: passage.entry,
+                        exit: None,
+                    });
+                }
+
+                if (passage.inside || dips) && !inside {
+                    let start = if dips { approach_end } else { 0.0 };
+                    let exit = previous.epoch + find_crossing(distance, threshold, start, 1.0) * dt;
+                    for index in passage.open.drain(..) {
+                        self.encounters[index].exit = Some(exit);
+                    }
+                    passage.entry = None;
+                }
+                passage.inside = inside;
+            }
+        }
+    }
+}
+
+// Bisect for where the distance, as a function of the fraction of the step, crosses the threshold
+// between lo and hi, over which it's taken to be monotonic.
+fn find_crossing(distance: impl Fn(f64) -> f64, threshold: f64, lo: f64, hi: f64) -> f64 {
+    let outside = |tau: f64| distance(tau) >= threshold;
+    let (mut lo, mut hi) = (lo, hi);
+    let rising = !outside(lo);
+    for _ in 0..60 {
+        let mid = 0.5 * (lo + hi);
+        if outside(mid) == rising {
+            hi = mid;
+        } else {
+            lo = mid;
+        }
+    }
+    0.5 * (lo + hi)
+}
+
+// Cubic Hermite interpolation of the relative state across a step, at a fraction tau of the step.
+pub(crate) fn hermite(r0: &Vector3<f64>, v0: &Vector3<f64>, r1: &Vector3<f64>, v1: &Vector3<f64>, dt: f64, tau: f64) -> (Vector3<f64>, Vector3<f64>) {
+    let t2 = tau * tau;
+    let t3 = t2 * tau;
+    let position = (2.0 * t3 - 3.0 * t2 + 1.0) * r0 + (t3 - 2.0 * t2 + tau) * dt * v0
+                 + (-2.0 * t3 + 3.0 * t2) * r1 + (t3 - t2) * dt * v1;
+    let velocity = ((6.0 * t2 - 6.0 * tau) * r0 + (6.0 * t2 - 6.0 * tau) * -r1) / dt
+                 + (3.0 * t2 - 4.0 * tau + 1.0) * v0 + (3.0 * t2 - 2.0 * tau) * v1;
+    (position, velocity)
+}
+
+// Bisect on the range rate of the interpolated relative motion.
+pub(crate) fn find_closest_approach(r0: &Vector3<f64>, v0: &Vector3<f64>, r1: &Vector3<f64>, v1: &Vector3<f64>, dt: f64) -> f64 {
+    let mut lo = 0.0;
+    let mut hi = 1.0;
+    for _ in 0..60 {
+        let mid = 0.5 * (lo + hi);
+        let (r, v) = hermite(r0, v0, r1, v1, dt, mid);
+        if r.dot(&v) * dt.signum() < 0.0 {
+            lo = mid;
+        } else {
+            hi = mid;
+        }
+    }
+    0.5 * (lo + hi)
+}
@@ -0,0 +1,110 @@
+use std::ffi::{CStr, CString};
+use std::fmt;
+use std::io;
+use std::os::raw::c_char;
+
+#[derive(Debug)]
+pub enum SpaceRocksError {
+    Spice(String),        // cspice's message, e.g. for a body or epoch outside the loaded kernels
+    UnknownFrame(String),
+    UnknownMass(String),  // a perturber whose GM isn't among the constants
+    MissingBody(String),  // a body the calculation needs isn't among the rocks
+    Integration(String),  // the integrator couldn't go on, e.g. at a singularity
+    InvalidInput(String),
+    Parse(String),
+    Io(io::Error),
+}
+
+pub type Result<T> = std::result::Result<T, SpaceRocksError>;
+
+impl fmt::Display for SpaceRocksError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SpaceRocksError::Spice(message) => write!(f, "spice error: {}", message),
+            SpaceRocksError::UnknownFrame(name) => write!(f, "unknown frame {}", name),
+            SpaceRocksError::UnknownMass(name) => write!(f, "no mass is known for {}", name),
+            SpaceRocksError::MissingBody(name) => write!(f, "{} must be among the rocks", name),
+            SpaceRocksError::Integration(message) => write!(f, "integration failed: {}", message),
+            SpaceRocksError::InvalidInput(message) => write!(f, "invalid input: {}", message),
+            SpaceRocksError::Parse(message) => write!(f, "parse error: {}", message),
+            SpaceRocksError::Io(error) => write!(f, "{}", error),
+        }
+    }
+}
+
+impl std::error::Error for SpaceRocksError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            SpaceRocksError::Io(error) => Some(error),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for SpaceRocksError {
+    fn from(error: io::Error) -> Self {
+        SpaceRocksError::Io(error)
+    }
+}
+
+#[cfg(feature = "serde")]
+impl From<csv::Error> for SpaceRocksError {
+    fn from(error: csv::Error) -> Self {
+        let message = error.to_string();
+        match error.into_kind() {
+            csv::ErrorKind::Io(error) => SpaceRocksError::Io(error),
+            _ => SpaceRocksError::Parse(message),
+        }
+    }
+}
+
+#[cfg(feature = "serde")]
+impl From<serde_json::Error> for SpaceRocksError {
+    fn from(error: serde_json::Error) -> Self {
+        if error.is_io() {
+            SpaceRocksError::Io(error.into())
+        } else {
+            SpaceRocksError::Parse(error.to_string())
+        }
+    }
+}
+
+#[cfg(feature = "arrow")]
+impl From<arrow::error::ArrowError> for SpaceRocksError {
+    fn from(error: arrow::error::ArrowError) -> Self {
+        match error {
+            arrow::error::ArrowError::IoError(_, error) => SpaceRocksError::Io(error),
+            error => SpaceRocksError::Parse(error.to_string()),
+        }
+    }
+}
+
+#[cfg(feature = "arrow")]
+impl From<parquet::errors::ParquetError> for SpaceRocksError {
+    fn from(error: parquet::errors::ParquetError) -> Self {
+        SpaceRocksError::Parse(error.to_string())
+    }
+}
+
+// Run spice calls with cspice set to return on error rather than abort the process, and turn an error
+// into its long message. cspice skips everything after an error until it's reset, so the outputs of a
+// failed call are garbage and dropped.
+pub fn checked<T>(call: impl FnOnce() -> T) -> Result<T> {
+    let set = CString::new("SET").unwrap();
+    let mut action = *b"RETURN\0";
+    unsafe { spice::c::erract_c(set.as_ptr() as _, 0, action.as_mut_ptr() as *mut c_char) };
+
+    let value = call();
+    if unsafe { spice::c::failed_c() } == 0 {
+        return Ok(value);
+    }
+
+    let long = CString::new("LONG").unwrap();
+    let mut message = vec![0u8; 1841];
+    let message = unsafe {
+        spice::c::getmsg_c(long.as_ptr() as _, message.len() as _, message.as_mut_ptr() as *mut c_char);
+        spice::c::reset_c();
+        CStr::from_ptr(message.as_ptr() as *const c_char).to_string_lossy().trim().to_string()
+    };
+    Err(SpaceRocksError::Spice(message))
+}
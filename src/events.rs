@@ -0,0 +1,203 @@
+use crate::spacerock::SpaceRock;
+use crate::forces::find_sun;
+
+use nalgebra::Vector3;
+
+// Changes to the set of rocks made during an integration.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Event {
+    Collision { epoch: f64, rocks: (String, String) }, // the first rock survives a merger or removal
+    Ejection { epoch: f64, rock: String, distance: f64 }, // heliocentric distance in au
+}
+
+impl Event {
+
+    pub fn epoch(&self) -> f64 {
+        match self {
+            Event::Collision { epoch, .. } => *epoch,
+            Event::Ejection { epoch, .. } => *epoch,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum CollisionHandler {
+    Halt,   // stop the integration at the collision
+    #[default]
+    Merge,  // replace the pair by one rock, conserving mass and momentum
+    Bounce, // elastic bounce along the line of centres
+    Remove, // remove the less massive rock
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum EjectionHandler {
+    Halt,
+    #[default]
+    Remove,
+}
+
+// What to do when two rocks touch (both need a physical radius, and at least one a mass),
+// and when a rock goes beyond the ejection distance from the sun.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Handlers {
+    pub collision: CollisionHandler,
+    pub ejection: EjectionHandler,
+    pub ejection_distance: f64, // heliocentric distance in au; infinite to never eject
+}
+
+impl Handlers {
+
+    pub fn new() -> Self {
+        Handlers {
+            collision: CollisionHandler::default(),
+            ejection: EjectionHandler::default(),
+            ejection_distance: f64::INFINITY,
+        }
+    }
+
+    pub fn with_collision(mut self, handler: CollisionHandler) -> Self {
+        self.collision = handler;
+        self
+    }
+
+    pub fn with_ejection(mut self, handler: EjectionHandler, distance: f64) -> Self {
+        self.ejection = handler;
+        self.ejection_distance = distance;
+        self
+    }
+
+    // Whether an event stops the integration.
+    pub fn halts(&self, event: &Event) -> bool {
+        match event {
+            Event::Collision { .. } => self.collision == CollisionHandler::Halt,
+            Event::Ejection { .. } => self.ejection == EjectionHandler::Halt,
+        }
+    }
+}
+
+impl Default for Handlers {
+    fn default() -> Self {
+        Handlers::new()
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) enum Found {
+    Collision(usize, usize),
+    Ejection(usize, f64),
+}
+
+// The first collision between approaching rocks, or the first ejection, in a set of states.
+pub(crate) fn find_event(rocks: &[SpaceRock], positions: &[Vector3<f64>], velocities: &[Vector3<f64>], handlers: &Handlers) -> Option<Found> {
+
+    for i in 0..rocks.len() {
+        let Some(ri) = rocks[i].radius else {
+            continue;
+        };
+        for j in i + 1..rocks.len() {
+            let Some(rj) = rocks[j].radius else {
+                continue;
+            };
+            if rocks[i].mass.is_none() && rocks[j].mass.is_none() {
+                continue;
+            }
+            let d = positions[i] - positions[j];
+            if d.norm() < ri + rj && d.dot(&(velocities[i] - velocities[j])) < 0.0 {
+                return Some(Found::Collision(i, j));
+            }
+        }
+    }
+
+    let sun = find_sun(rocks);
+    let centre = sun.map_or(Vector3::zeros(), |k| positions[k]);
+    for i in (0..rocks.len()).filter(|&i| Some(i) != sun) {
+        let distance = (positions[i] - centre).norm();
+        if distance > handlers.ejection_distance {
+            return Some(Found::Ejection(i, distance));
+        }
+    }
+
+    None
+}
+
+// Apply the handler for an event to rocks whose states are current, returning the event for the log
+// and the indices of any rocks that were removed.
+pub(crate) fn resolve(rocks: &mut Vec<SpaceRock>, found: Found, epoch: f64, handlers: &Handlers) -> (Event, Vec<usize>) {
+
+    match found {
+        Found::Ejection(i, distance) => {
+            let event = Event::Ejection { epoch, rock: rocks[i].name.clone(), distance };
+            if handlers.ejection == EjectionHandler::Halt {
+                return (event, Vec::new());
+            }
+            rocks.remove(i);
+            (event, vec![i])
+        }
+        Found::Collision(i, j) => {
+            // the more massive rock comes first
+            let (a, b) = if rocks[j].mass.unwrap_or(0.0) > rocks[i].mass.unwrap_or(0.0) { (j, i) } else { (i, j) };
+            let event = Event::Collision { epoch, rocks: (rocks[a].name.clone(), rocks[b].name.clone()) };
+            let (ma, mb) = (rocks[a].mass.unwrap_or(0.0), rocks[b].mass.unwrap_or(0.0));
+            // the fractions of the pair's mass in each, taken as equal between test particles
+            let (wa, wb) = if ma + mb > 0.0 { (ma / (ma + mb), mb / (ma + mb)) } else { (0.5, 0.5) };
+
+            match handlers.collision {
+                CollisionHandler::Halt => (event, Vec::new()),
+                CollisionHandler::Remove => {
+                    rocks.remove(b);
+                    (event, vec![b])
+                }
+                CollisionHandler::Bounce => {
+                    let n = (rocks[a].position - rocks[b].position).normalize();
+                    let vn = (rocks[a].velocity - rocks[b].velocity).dot(&n);
+                    rocks[a].velocity -= 2.0 * wb * vn * n;
+                    rocks[b].velocity += 2.0 * wa * vn * n;
+                    (event, Vec::new())
+                }
+                CollisionHandler::Merge => {
+                    let other = rocks.remove(b);
+                    let rock = &mut rocks[if a > b { a - 1 } else { a }];
+                    rock.position = wa * rock.position + wb * other.position;
+                    rock.velocity = wa * rock.velocity + wb * other.velocity;
+                    // two test particles merge into a test particle
+                    rock.mass = rock.mass.or(other.mass).map(|_| ma + mb);
+                    if let (Some(ra), Some(rb)) = (rock.radius, other.radius) {
+                        rock.radius = Some((ra * ra * ra + rb * rb * rb).cbrt());
+                    }
+                    (event, vec![b])
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::statevector::StateVector;
+
+    fn pair() -> Vec<SpaceRock> {
+        let a = SpaceRock::from_state("a", StateVector::new(1.0, 0.0, 0.0, 0.0, 0.01, 0.0), 2460000.5);
+        let b = SpaceRock::from_state("b", StateVector::new(1.0001, 0.0, 0.0, -0.001, 0.01, 0.0), 2460000.5);
+        vec![a, b]
+    }
+
+    #[test]
+    fn test_particles_merge_into_a_test_particle() {
+        let mut rocks = pair();
+        resolve(&mut rocks, Found::Collision(0, 1), 2460000.5, &Handlers::new().with_collision(CollisionHandler::Merge));
+        assert_eq!(rocks.len(), 1);
+        assert_eq!(rocks[0].mass, None);
+        assert!((rocks[0].position.x - 1.00005).abs() < 1e-12);
+        assert!((rocks[0].velocity.x + 0.0005).abs() < 1e-12);
+    }
+
+    #[test]
+    fn test_particles_bounce_as_equal_masses() {
+        let mut rocks = pair();
+        resolve(&mut rocks, Found::Collision(0, 1), 2460000.5, &Handlers::new().with_collision(CollisionHandler::Bounce));
+        assert!((rocks[0].velocity.x + 0.001).abs() < 1e-15);
+        assert!(rocks[1].velocity.x.abs() < 1e-15);
+        assert_eq!((rocks[0].mass, rocks[1].mass), (None, None));
+    }
+}
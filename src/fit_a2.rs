@@ -0,0 +1,122 @@
+use crate::spacerock::SpaceRock;
+use crate::frame::Frame;
+use crate::error::Result;
+use crate::detection::Detection;
+use crate::forces::ForceModel;
+use crate::integrate::{integrate, RK45};
+use crate::nongravs::NonGravs;
+use crate::covariance::Covariance;
+
+use nalgebra::{DMatrix, DVector, Vector3};
+
+use std::f64::consts::PI;
+
+// finite-difference steps for x, y, z, vx, vy, vz and A2
+const STEPS: [f64; 7] = [1e-7, 1e-7, 1e-7, 1e-9, 1e-9, 1e-9, 1e-13];
+
+// Fit the state of a rock together with its transverse non-gravitational acceleration A2
+// to a long arc of detections by differential correction. The perturbers are integrated
+// alongside the rock, and must share its epoch. Returns the fitted rock, carrying the
+// covariance of its state, and the 1-sigma uncertainty on A2, given the astrometric
+// uncertainty of each detection in radians. None if the fit doesn't converge.
+pub fn fit_a2(rock: &SpaceRock, perturbers: &[SpaceRock], detections: &[&Detection], forces: &ForceModel, integrator: &RK45, astrometric_uncertainty: f64) -> Result<Option<(SpaceRock, f64)>> {
+
+    if detections.len() < 4 {
+        return Ok(None);
+    }
+
+    let forces = forces.clone().with_nongravs();
+
+    let mut rock = rock.clone();
+    rock.change_frame(&Frame::J2000)?;
+    let nongravs = rock.nongravs.unwrap_or(NonGravs::new(0.0, 0.0));
+
+    let mut params = DVector::from_vec(vec![rock.position.x, rock.position.y, rock.position.z,
+                                            rock.velocity.x, rock.velocity.y, rock.velocity.z,
+                                            nongravs.a2]);
+
+    let observed: Vec<[f64; 2]> = detections.iter().map(|d| [d.ra.to_radians(), d.dec.to_radians()]).collect();
+
+    for _ in 0..10 {
+
+        let residuals = DVector::from_vec(residuals_for(&with_params(&rock, &params), perturbers, detections, &forces, integrator, &observed)?);
+
+        let mut jacobian = DMatrix::zeros(residuals.len(), 7);
+        for k in 0..7 {
+            let mut plus = params.clone();
+            let mut minus = params.clone();
+            plus[k] += STEPS[k];
+            minus[k] -= STEPS[k];
+            let res_plus = residuals_for(&with_params(&rock, &plus), perturbers, detections, &forces, integrator, &observed)?;
+            let res_minus = residuals_for(&with_params(&rock, &minus), perturbers, detections, &forces, integrator, &observed)?;
+            for i in 0..residuals.len() {
+                // the residuals are observed - predicted, so the sign flips
+                jacobian[(i, k)] = -(res_plus[i] - res_minus[i]) / (2.0 * STEPS[k]);
+            }
+        }
+
+        let normal = jacobian.transpose() * &jacobian;
+        let Some(normal_inv) = normal.clone().try_inverse() else {
+            return Ok(None);
+        };
+        let correction = &normal_inv * (jacobian.transpose() * &residuals);
+        params += &correction;
+
+        let converged = (0..7).all(|k| correction[k].abs() < 1e-3 * STEPS[k]);
+        if converged {
+            let variance = astrometric_uncertainty * astrometric_uncertainty;
+            let mut fitted = with_params(&rock, &params);
+            fitted.covariance = Some(Covariance::Cartesian(normal_inv.fixed_view::<6, 6>(0, 0) * variance));
+            return Ok(Some((fitted, (normal_inv[(6, 6)] * variance).sqrt())));
+        }
+    }
+
+    Ok(None)
+}
+
+fn with_params(rock: &SpaceRock, params: &DVector<f64>) -> SpaceRock {
+    let mut rock = rock.clone();
+    rock.position = Vector3::new(params[0], params[1], params[2]);
+    rock.velocity = Vector3::new(params[3], params[4], params[5]);
+    let area_to_mass = rock.nongravs.map_or(0.0, |nongravs| nongravs.area_to_mass);
+    rock.nongravs = Some(NonGravs::new(params[6], area_to_mass));
+    rock.covariance = None;
+    rock
+}
+
+fn residuals_for(rock: &SpaceRock, perturbers: &[SpaceRock], detections: &[&Detection], forces: &ForceModel, integrator: &RK45, observed: &[[f64; 2]]) -> Result<Vec<f64>> {
+    Ok(residuals(observed, &predict(rock, perturbers, detections, forces, integrator)?))
+}
+
+fn residuals(observed: &[[f64; 2]], predicted: &[[f64; 2]]) -> Vec<f64> {
+    let mut res = Vec::with_capacity(2 * observed.len());
+    for (obs, pred) in observed.iter().zip(predicted) {
+        let d_ra = (obs[0] - pred[0] + PI).rem_euclid(2.0 * PI) - PI;
+        res.push(d_ra * obs[1].cos());
+        res.push(obs[1] - pred[1]);
+    }
+    res
+}
+
+// Integrate the rock forward and backward from its epoch through the epochs of the detections.
+fn predict(rock: &SpaceRock, perturbers: &[SpaceRock], detections: &[&Detection], forces: &ForceModel, integrator: &RK45) -> Result<Vec<[f64; 2]>> {
+
+    let mut order: Vec<usize> = (0..detections.len()).collect();
+    order.sort_by(|&i, &j| detections[i].epoch.total_cmp(&detections[j].epoch));
+
+    let forward: Vec<usize> = order.iter().copied().filter(|&i| detections[i].epoch >= rock.epoch).collect();
+    let backward: Vec<usize> = order.iter().rev().copied().filter(|&i| detections[i].epoch < rock.epoch).collect();
+
+    let mut predicted = vec![[0.0; 2]; detections.len()];
+    for pass in [forward, backward] {
+        let mut system: Vec<SpaceRock> = perturbers.to_vec();
+        system.push(rock.clone());
+        for i in pass {
+            integrate(&mut system, forces, integrator, detections[i].epoch)?;
+            let mut target = system[system.len() - 1].clone();
+            predicted[i] = target.observe(&detections[i].observer)?;
+        }
+    }
+
+    Ok(predicted)
+}
@@ -0,0 +1,246 @@
+use crate::spacerock::SpaceRock;
+use crate::relativity::{Relativity, schwarzschild_correction, eih_corrections};
+use crate::constants::{GM_SUN, KM_TO_AU, MASSES, SECONDS_PER_DAY};
+use crate::error::{checked, Result, SpaceRocksError};
+use crate::nongravs::NonGravs;
+
+use nalgebra::{Matrix3, Matrix3x2, Vector3};
+
+use std::cell::RefCell;
+use std::rc::Rc;
+
+// Rocks with a mass attract every other rock; rocks without one are test particles. Perturbers
+// are massive bodies whose states are read from the loaded spice kernels at every substep
+// rather than integrated, so they shouldn't also appear among the rocks.
+#[derive(Clone, Debug, Default)]
+pub struct ForceModel {
+    pub nongravs: bool,
+    pub relativity: Relativity,
+    pub perturbers: Vec<String>,
+}
+
+impl ForceModel {
+
+    pub fn new() -> Self {
+        ForceModel::default()
+    }
+
+    pub fn with_nongravs(mut self) -> Self {
+        self.nongravs = true;
+        self
+    }
+
+    pub fn with_relativity(mut self, relativity: Relativity) -> Self {
+        self.relativity = relativity;
+        self
+    }
+
+    pub fn with_perturbers(mut self, names: &[&str]) -> Result<Self> {
+        for name in names {
+            if !MASSES.contains_key(&name.to_lowercase()) {
+                return Err(SpaceRocksError::UnknownMass(name.to_string()));
+            }
+            self.perturbers.push(name.to_string());
+        }
+        Ok(self)
+    }
+
+    // As SpaceRock::from_spice for each perturber, but converting the epoch once and checking for spice
+    // errors once, since the integrators call this at every stage of every step.
+    pub fn perturber_states(&self, epoch: f64) -> Result<Vec<SpaceRock>> {
+        if self.perturbers.is_empty() {
+            return Ok(Vec::new());
+        }
+        let states = checked(|| {
+            let et = spice::str2et(&format!("JD{epoch} UTC", epoch=epoch));
+            self.perturbers.iter().map(|name| spice::spkezr(name, et, "J2000", "NONE", "SSB").0).collect::<Vec<_>>()
+        })?;
+        Ok(self.perturbers.iter().zip(states).map(|(name, state)| {
+            let mut body = SpaceRock::from_xyz(name, state[0] * KM_TO_AU, state[1] * KM_TO_AU, state[2] * KM_TO_AU,
+                                               state[3] * KM_TO_AU * SECONDS_PER_DAY, state[4] * KM_TO_AU * SECONDS_PER_DAY,
+                                               state[5] * KM_TO_AU * SECONDS_PER_DAY, epoch);
+            body.mass = MASSES.get(&name.to_lowercase()).copied();
+            body
+        }).collect())
+    }
+
+    // The integrators read the perturbers at both ends of the span before they start, so the kernels
+    // cover every substep in between.
+    pub(crate) fn check_perturbers(&self, start: f64, end: f64) -> Result<()> {
+        self.perturber_states(start)?;
+        self.perturber_states(end)?;
+        Ok(())
+    }
+
+    pub fn accelerations(&self, rocks: &[SpaceRock], positions: &[Vector3<f64>], velocities: &[Vector3<f64>], epoch: f64) -> Result<Vec<Vector3<f64>>> {
+        Ok(self.evaluate(rocks, &self.perturber_states(epoch)?, positions, velocities, false).0)
+    }
+
+    // Accelerations along with their partial derivatives for the variational equations. Each rock's
+    // partials treat the other bodies as fixed, and the relativistic partials use the sun's term only.
+    pub fn accelerations_and_partials(&self, rocks: &[SpaceRock], positions: &[Vector3<f64>], velocities: &[Vector3<f64>], epoch: f64) -> Result<(Vec<Vector3<f64>>, Vec<Partials>)> {
+        Ok(self.evaluate(rocks, &self.perturber_states(epoch)?, positions, velocities, true))
+    }
+
+    // As above, with the perturbers' states already read.
+    pub(crate) fn evaluate(&self, rocks: &[SpaceRock], perturbers: &[SpaceRock], positions: &[Vector3<f64>], velocities: &[Vector3<f64>], with_partials: bool) -> (Vec<Vector3<f64>>, Vec<Partials>) {
+
+        let mut masses: Vec<Option<f64>> = rocks.iter().map(|rock| rock.mass).collect();
+        let mut positions = positions.to_vec();
+        let mut velocities = velocities.to_vec();
+        let mut sun = find_sun(rocks);
+
+        for body in perturbers {
+            if body.name.eq_ignore_ascii_case("sun") {
+                sun = Some(masses.len());
+            }
+            masses.push(body.mass);
+            positions.push(body.position);
+            velocities.push(body.velocity);
+        }
+
+        let n = masses.len();
+        let mut acc = vec![Vector3::zeros(); n];
+        let mut partials = if with_partials { vec![Partials::zeros(); rocks.len()] } else { Vec::new() };
+
+        for j in 0..n {
+            if let Some(gm) = masses[j] {
+                for i in 0..n {
+                    if i == j {
+                        continue;
+                    }
+                    let d_pos = positions[i] - positions[j];
+                    let r = d_pos.norm();
+                    let r3 = r * r * r;
+                    acc[i] -= gm * d_pos / r3;
+                    if with_partials && i < rocks.len() {
+                        partials[i].position += gm * (3.0 * d_pos * d_pos.transpose() / (r3 * r * r) - Matrix3::identity() / r3);
+                    }
+                }
+            }
+        }
+
+        // heliocentric terms fall back to a sun at the origin if it isn't among the bodies
+        let (sun_pos, sun_vel, sun_gm) = match sun {
+            Some(k) => (positions[k], velocities[k], masses[k].unwrap_or(GM_SUN)),
+            None => (Vector3::zeros(), Vector3::zeros(), GM_SUN),
+        };
+
+        match self.relativity {
+            Relativity::Newtonian => {}
+            Relativity::Schwarzschild => {
+                for i in 0..rocks.len() {
+                    if Some(i) != sun {
+                        acc[i] += schwarzschild_correction(sun_gm, &(positions[i] - sun_pos), &(velocities[i] - sun_vel));
+                    }
+                }
+            }
+            Relativity::EIH => {
+                let corrections = eih_corrections(&masses, &positions, &velocities, &acc);
+                for i in 0..rocks.len() {
+                    acc[i] += corrections[i];
+                }
+            }
+        }
+
+        if with_partials && self.relativity != Relativity::Newtonian {
+            for i in 0..rocks.len() {
+                if Some(i) != sun {
+                    let (d_pos, d_vel) = local_partials(|r, v| schwarzschild_correction(sun_gm, r, v), &(positions[i] - sun_pos), &(velocities[i] - sun_vel));
+                    partials[i].position += d_pos;
+                    partials[i].velocity += d_vel;
+                }
+            }
+        }
+
+        if self.nongravs {
+            for i in (0..rocks.len()).filter(|&i| Some(i) != sun) {
+                let helio_pos = positions[i] - sun_pos;
+                let helio_vel = velocities[i] - sun_vel;
+                if let Some(nongravs) = &rocks[i].nongravs {
+                    acc[i] += nongravs.acceleration(&helio_pos, &helio_vel);
+                    if with_partials {
+                        let (d_pos, d_vel) = local_partials(|r, v| nongravs.acceleration(r, v), &helio_pos, &helio_vel);
+                        partials[i].position += d_pos;
+                        partials[i].velocity += d_vel;
+                    }
+                }
+                if with_partials {
+                    partials[i].nongravs = NonGravs::partials(&helio_pos, &helio_vel);
+                }
+            }
+        }
+
+        acc.truncate(rocks.len());
+        (acc, partials)
+    }
+}
+
+// The perturbers' states at the last epoch they were read for. An integration step ends with a stage at
+// its final epoch, so whatever watches the steps can reuse those states rather than read them again.
+pub(crate) struct PerturberCache<'a> {
+    forces: &'a ForceModel,
+    last: RefCell<Option<(f64, Rc<Vec<SpaceRock>>)>>,
+}
+
+impl<'a> PerturberCache<'a> {
+
+    pub(crate) fn new(forces: &'a ForceModel) -> Self {
+        PerturberCache { forces, last: RefCell::new(None) }
+    }
+
+    pub(crate) fn states(&self, epoch: f64) -> Result<Rc<Vec<SpaceRock>>> {
+        let mut last = self.last.borrow_mut();
+        match last.as_ref() {
+            Some((cached, states)) if *cached == epoch => Ok(states.clone()),
+            _ => {
+                let states = Rc::new(self.forces.perturber_states(epoch)?);
+                *last = Some((epoch, states.clone()));
+                Ok(states)
+            }
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Partials {
+    pub position: Matrix3<f64>,   // d(acceleration) / d(position)
+    pub velocity: Matrix3<f64>,   // d(acceleration) / d(velocity)
+    pub nongravs: Matrix3x2<f64>, // d(acceleration) / d(a2, area_to_mass)
+}
+
+impl Partials {
+    pub fn zeros() -> Self {
+        Partials {
+            position: Matrix3::zeros(),
+            velocity: Matrix3::zeros(),
+            nongravs: Matrix3x2::zeros(),
+        }
+    }
+}
+
+// Central-difference partials of a cheap acceleration term with respect to position and velocity.
+fn local_partials<F>(f: F, position: &Vector3<f64>, velocity: &Vector3<f64>) -> (Matrix3<f64>, Matrix3<f64>)
+where
+    F: Fn(&Vector3<f64>, &Vector3<f64>) -> Vector3<f64>,
+{
+    let h_pos = 1e-7 * position.norm().max(1e-6);
+    let h_vel = 1e-7 * velocity.norm().max(1e-8);
+
+    let mut d_pos = Matrix3::zeros();
+    let mut d_vel = Matrix3::zeros();
+    for k in 0..3 {
+        let mut step = Vector3::zeros();
+        step[k] = h_pos;
+        d_pos.set_column(k, &((f(&(position + step), velocity) - f(&(position - step), velocity)) / (2.0 * h_pos)));
+
+        let mut step = Vector3::zeros();
+        step[k] = h_vel;
+        d_vel.set_column(k, &((f(position, &(velocity + step)) - f(position, &(velocity - step))) / (2.0 * h_vel)));
+    }
+    (d_pos, d_vel)
+}
+
+pub fn find_sun(rocks: &[SpaceRock]) -> Option<usize> {
+    rocks.iter().position(|rock| rock.name.eq_ignore_ascii_case("sun"))
+}
@@ -0,0 +1,477 @@
+use crate::constants::*;
+use crate::error::{checked, Result, SpaceRocksError};
+use crate::pole::Pole;
+
+use lazy_static::lazy_static;
+use nalgebra::{Matrix3, Matrix6, Vector3};
+
+use std::collections::HashMap;
+use std::fmt;
+use std::str::FromStr;
+use std::sync::RwLock;
+
+const ARCSEC_TO_RAD: f64 = DEG_TO_RAD / 3600.0;
+
+// Reference frames, each a rotation from J2000. J2000 stands in for the ICRF, from which it differs by
+// milliarcseconds. Custom frames carry their own rotation, so a frame can't be missing at use.
+//
+// The frames of date are evaluated at the epoch of the rock they hold, taken as TT for precession and
+// nutation and as UT1 for the earth's rotation; the differences from UTC are below their accuracy here.
+//
+// A frame is only a set of axes: the rotating ones (ITRF, BodyFixed, Synodic) turn about the origin of the
+// rock they hold, so move the rock onto the body or primary with change_origin before changing into them.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Frame {
+    J2000,
+    EclipJ2000,
+    FK4,
+    Galactic,
+    Invariable,
+    MeanEquatorOfDate, // IAU 1976 precession
+    TrueEquatorOfDate, // and IAU 1980 nutation
+    MeanEclipticOfDate,
+    TEME,              // true equator, mean equinox, as used by SGP4 for TLEs
+    ITRF,              // earth-fixed, neglecting polar motion
+    BodyEquator(Pole), // a body's equator, with x toward its ascending node on the J2000 equator
+    BodyFixed(Pole),   // turning with the body, x along its prime meridian
+    Synodic { primary: String, secondary: String }, // x from primary to secondary, z along their orbit normal
+    Custom { name: String, rotation: Matrix3<f64> },
+}
+
+lazy_static! {
+    static ref CUSTOM_FRAMES: RwLock<HashMap<String, Matrix3<f64>>> = RwLock::new(HashMap::new());
+}
+
+impl Frame {
+
+    // Add a frame, given the rotation from J2000 into it, so that it can be parsed by name. None if the name is
+    // taken by a built-in frame or the matrix isn't a rotation.
+    pub fn register(name: &str, rotation: Matrix3<f64>) -> Option<Frame> {
+        if builtin(name).is_some() || (rotation * rotation.transpose() - Matrix3::identity()).abs().max() > 1e-12 || rotation.determinant() < 0.0 {
+            return None;
+        }
+        CUSTOM_FRAMES.write().unwrap_or_else(|e| e.into_inner()).insert(name.to_uppercase(), rotation);
+        Some(Frame::Custom { name: name.to_string(), rotation })
+    }
+
+    pub fn name(&self) -> String {
+        match self {
+            Frame::J2000 => "J2000".to_string(),
+            Frame::EclipJ2000 => "ECLIPJ2000".to_string(),
+            Frame::FK4 => "FK4".to_string(),
+            Frame::Galactic => "GALACTIC".to_string(),
+            Frame::Invariable => "INVARIABLE".to_string(),
+            Frame::MeanEquatorOfDate => "MOD".to_string(),
+            Frame::TrueEquatorOfDate => "TOD".to_string(),
+            Frame::MeanEclipticOfDate => "ECLIPDATE".to_string(),
+            Frame::TEME => "TEME".to_string(),
+            Frame::ITRF => "ITRF".to_string(),
+            Frame::BodyEquator(pole) => format!("{}_EQUATOR", pole.body.to_uppercase()),
+            Frame::BodyFixed(pole) => format!("IAU_{}", pole.body.to_uppercase()),
+            Frame::Synodic { primary, secondary } => format!("SYNODIC({},{})", primary, secondary),
+            Frame::Custom { name, .. } => name.clone(),
+        }
+    }
+
+    // The rotation taking J2000 vectors into this frame at an epoch. Only the body and synodic frames
+    // can fail, on the spice calls for their time scale and states.
+    pub fn rotation(&self, epoch: f64) -> Result<Matrix3<f64>> {
+        let rotation = match self {
+            Frame::J2000 => ROTATION_J2000,
+            Frame::EclipJ2000 => ROTATION_ECLIPJ2000,
+            Frame::FK4 => ROTATION_FK4,
+            Frame::Galactic => ROTATION_GALACTIC,
+            Frame::Invariable => ROTATION_INVARIABLE,
+            Frame::MeanEquatorOfDate => precession(epoch),
+            Frame::TrueEquatorOfDate => nutation(epoch) * precession(epoch),
+            Frame::MeanEclipticOfDate => rotate_x(mean_obliquity(epoch)) * precession(epoch),
+            Frame::TEME => rotate_z(equation_of_equinoxes(epoch)) * nutation(epoch) * precession(epoch),
+            Frame::ITRF => rotate_z(gmst(epoch) + equation_of_equinoxes(epoch)) * nutation(epoch) * precession(epoch),
+            Frame::BodyEquator(pole) => {
+                let (ra, dec, _) = pole.orientation(tdb(epoch)?);
+                equator(ra, dec)
+            }
+            Frame::BodyFixed(pole) => {
+                let (ra, dec, w) = pole.orientation(tdb(epoch)?);
+                rotate_z(w) * equator(ra, dec)
+            }
+            Frame::Synodic { primary, secondary } => {
+                let (x, _, z) = synodic_axes(primary, secondary, epoch)?;
+                let y = z.cross(&x);
+                Matrix3::from_rows(&[x.transpose(), y.transpose(), z.transpose()])
+            }
+            Frame::Custom { rotation, .. } => *rotation,
+        };
+        Ok(rotation)
+    }
+
+    // The time derivative of the rotation, per day. Only the spin of the frames turning with a body or pair
+    // is fast enough to matter; the precession and nutation rates, and the drift of poles, are neglected.
+    pub fn rotation_rate(&self, epoch: f64) -> Result<Matrix3<f64>> {
+        let rate = match self {
+            Frame::ITRF => {
+                let theta = gmst(epoch) + equation_of_equinoxes(epoch);
+                rotate_z_rate(theta) * gmst_rate(epoch) * nutation(epoch) * precession(epoch)
+            }
+            Frame::BodyFixed(pole) => {
+                let (ra, dec, w) = pole.orientation(tdb(epoch)?);
+                rotate_z_rate(w) * pole.spin_rate() * equator(ra, dec)
+            }
+            Frame::Synodic { primary, secondary } => {
+                // the axes turn at h / r^2 about z, so x' = |ω| y and y' = -|ω| x
+                let (x, omega, z) = synodic_axes(primary, secondary, epoch)?;
+                let y = z.cross(&x);
+                Matrix3::from_rows(&[y.transpose() * omega, -x.transpose() * omega, Vector3::zeros().transpose()])
+            }
+            _ => Matrix3::zeros(),
+        };
+        Ok(rate)
+    }
+
+    // The linear map taking a state in this frame into another at an epoch, with the velocity picking up
+    // the motion of the frames: v' = A v + B r.
+    pub fn transformation_to(&self, other: &Frame, epoch: f64) -> Result<Matrix6<f64>> {
+        let from = self.rotation(epoch)?;
+        let to = other.rotation(epoch)?;
+        let a = to * from.transpose();
+        let b = to * self.rotation_rate(epoch)?.transpose() + other.rotation_rate(epoch)? * from.transpose();
+        let mut transformation = Matrix6::zeros();
+        transformation.fixed_view_mut::<3, 3>(0, 0).copy_from(&a);
+        transformation.fixed_view_mut::<3, 3>(3, 0).copy_from(&b);
+        transformation.fixed_view_mut::<3, 3>(3, 3).copy_from(&a);
+        Ok(transformation)
+    }
+
+    // Move a position and velocity from this frame into another.
+    pub fn transform(&self, other: &Frame, epoch: f64, position: &Vector3<f64>, velocity: &Vector3<f64>) -> Result<(Vector3<f64>, Vector3<f64>)> {
+        let transformation = self.transformation_to(other, epoch)?;
+        let a = transformation.fixed_view::<3, 3>(0, 0);
+        let b = transformation.fixed_view::<3, 3>(3, 0);
+        Ok((a * position, a * velocity + b * position))
+    }
+}
+
+impl FromStr for Frame {
+    type Err = SpaceRocksError;
+
+    // Case-insensitive, with ICRF and ICRS taken as J2000. Body frames go by IAU_<BODY> and <BODY>_EQUATOR,
+    // and synodic frames by SYNODIC(<PRIMARY>,<SECONDARY>).
+    fn from_str(name: &str) -> std::result::Result<Self, Self::Err> {
+        if let Some(frame) = builtin(name) {
+            return Ok(frame);
+        }
+        let frames = CUSTOM_FRAMES.read().unwrap_or_else(|e| e.into_inner());
+        match frames.get(&name.to_uppercase()) {
+            Some(rotation) => Ok(Frame::Custom { name: name.to_string(), rotation: *rotation }),
+            None => Err(SpaceRocksError::UnknownFrame(name.to_string())),
+        }
+    }
+}
+
+impl fmt::Display for Frame {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+// Frames go by name, so a custom frame has to be registered before it can be read back.
+#[cfg(feature = "serde")]
+impl serde::Serialize for Frame {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error> {
+        serializer.serialize_str(&self.name())
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de> serde::Deserialize<'de> for Frame {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> std::result::Result<Self, D::Error> {
+        let name = String::deserialize(deserializer)?;
+        name.parse().map_err(serde::de::Error::custom)
+    }
+}
+
+fn builtin(name: &str) -> Option<Frame> {
+    match name.to_uppercase().as_str() {
+        "J2000" | "ICRF" | "ICRS" => Some(Frame::J2000),
+        "ECLIPJ2000" => Some(Frame::EclipJ2000),
+        "FK4" => Some(Frame::FK4),
+        "GALACTIC" => Some(Frame::Galactic),
+        "INVARIABLE" => Some(Frame::Invariable),
+        "MOD" => Some(Frame::MeanEquatorOfDate),
+        "TOD" => Some(Frame::TrueEquatorOfDate),
+        "ECLIPDATE" => Some(Frame::MeanEclipticOfDate),
+        "TEME" => Some(Frame::TEME),
+        "ITRF" | "ITRF93" => Some(Frame::ITRF),
+        upper => {
+            if let Some(body) = upper.strip_prefix("IAU_") {
+                return Pole::of(body).map(Frame::BodyFixed);
+            }
+            if let Some(body) = upper.strip_suffix("_EQUATOR") {
+                return Pole::of(body).map(Frame::BodyEquator);
+            }
+            // the body names as given, since they're passed on to spice
+            let name = name.trim();
+            if !name.get(..8).is_some_and(|prefix| prefix.eq_ignore_ascii_case("SYNODIC(")) || !name.ends_with(')') {
+                return None;
+            }
+            let (primary, secondary) = name[8..name.len() - 1].split_once(',')?;
+            Some(Frame::Synodic { primary: primary.trim().to_string(), secondary: secondary.trim().to_string() })
+        }
+    }
+}
+
+// The pole models run on TDB, which is more than a minute from UTC: a degree of jupiter's rotation.
+fn tdb(epoch: f64) -> Result<f64> {
+    let et = checked(|| spice::str2et(&format!("JD{epoch} UTC", epoch=epoch)))?;
+    Ok(2451545.0 + et / SECONDS_PER_DAY)
+}
+
+// From J2000 to a body's equator, given the right ascension and declination of its pole.
+fn equator(ra: f64, dec: f64) -> Matrix3<f64> {
+    rotate_x(0.5 * std::f64::consts::PI - dec) * rotate_z(0.5 * std::f64::consts::PI + ra)
+}
+
+// The direction from primary to secondary, the rate they turn at about each other in radians per day, and the
+// normal to their orbit, in J2000.
+fn synodic_axes(primary: &str, secondary: &str, epoch: f64) -> Result<(Vector3<f64>, f64, Vector3<f64>)> {
+    let (state, _) = checked(|| {
+        let et = spice::str2et(&format!("JD{epoch} UTC", epoch=epoch));
+        spice::spkezr(secondary, et, "J2000", "NONE", primary)
+    })?;
+    let r = Vector3::new(state[0], state[1], state[2]) * KM_TO_AU;
+    let v = Vector3::new(state[3], state[4], state[5]) * KM_TO_AU * SECONDS_PER_DAY;
+    let h = r.cross(&v);
+    Ok((r.normalize(), h.norm() / r.norm_squared(), h.normalize()))
+}
+
+// Rotations of the coordinate axes by an angle about x and z.
+fn rotate_x(angle: f64) -> Matrix3<f64> {
+    let (s, c) = angle.sin_cos();
+    Matrix3::new(1.0, 0.0, 0.0,
+                 0.0, c, s,
+                 0.0, -s, c)
+}
+
+fn rotate_y(angle: f64) -> Matrix3<f64> {
+    let (s, c) = angle.sin_cos();
+    Matrix3::new(c, 0.0, -s,
+                 0.0, 1.0, 0.0,
+                 s, 0.0, c)
+}
+
+fn rotate_z(angle: f64) -> Matrix3<f64> {
+    let (s, c) = angle.sin_cos();
+    Matrix3::new(c, s, 0.0,
+                 -s, c, 0.0,
+                 0.0, 0.0, 1.0)
+}
+
+// The derivative of rotate_z with respect to its angle.
+fn rotate_z_rate(angle: f64) -> Matrix3<f64> {
+    let (s, c) = angle.sin_cos();
+    Matrix3::new(-s, c, 0.0,
+                 -c, -s, 0.0,
+                 0.0, 0.0, 0.0)
+}
+
+fn centuries(epoch: f64) -> f64 {
+    (epoch - 2451545.0) / 36525.0
+}
+
+// Lieske et al. (1977), from the mean equator and equinox of J2000 to those of date.
+fn precession(epoch: f64) -> Matrix3<f64> {
+    let t = centuries(epoch);
+    let zeta = (2306.2181 * t + 0.30188 * t * t + 0.017998 * t * t * t) * ARCSEC_TO_RAD;
+    let z = (2306.2181 * t + 1.09468 * t * t + 0.018203 * t * t * t) * ARCSEC_TO_RAD;
+    let theta = (2004.3109 * t - 0.42665 * t * t - 0.041833 * t * t * t) * ARCSEC_TO_RAD;
+    rotate_z(-z) * rotate_y(theta) * rotate_z(-zeta)
+}
+
+fn mean_obliquity(epoch: f64) -> f64 {
+    let t = centuries(epoch);
+    (84381.448 - 46.8150 * t - 0.00059 * t * t + 0.001813 * t * t * t) * ARCSEC_TO_RAD
+}
+
+// Multiples of D, M, M', F and Ω, then the sine coefficients of Δψ and cosine coefficients of Δε with their
+// rates per century, in 0.0001". The terms of the IAU 1980 series down to 1.6 mas (Meeus, table 22.A).
+const NUTATION: [([f64; 5], [f64; 4]); 30] = [
+    ([0.0, 0.0, 0.0, 0.0, 1.0], [-171996.0, -174.2, 92025.0, 8.9]),
+    ([-2.0, 0.0, 0.0, 2.0, 2.0], [-13187.0, -1.6, 5736.0, -3.1]),
+    ([0.0, 0.0, 0.0, 2.0, 2.0], [-2274.0, -0.2, 977.0, -0.5]),
+    ([0.0, 0.0, 0.0, 0.0, 2.0], [2062.0, 0.2, -895.0, 0.5]),
+    ([0.0, 1.0, 0.0, 0.0, 0.0], [1426.0, -3.4, 54.0, -0.1]),
+    ([0.0, 0.0, 1.0, 0.0, 0.0], [712.0, 0.1, -7.0, 0.0]),
+    ([-2.0, 1.0, 0.0, 2.0, 2.0], [-517.0, 1.2, 224.0, -0.6]),
+    ([0.0, 0.0, 0.0, 2.0, 1.0], [-386.0, -0.4, 200.0, 0.0]),
+    ([0.0, 0.0, 1.0, 2.0, 2.0], [-301.0, 0.0, 129.0, -0.1]),
+    ([-2.0, -1.0, 0.0, 2.0, 2.0], [217.0, -0.5, -95.0, 0.3]),
+    ([-2.0, 0.0, 1.0, 0.0, 0.0], [-158.0, 0.0, 0.0, 0.0]),
+    ([-2.0, 0.0, 0.0, 2.0, 1.0], [129.0, 0.1, -70.0, 0.0]),
+    ([0.0, 0.0, -1.0, 2.0, 2.0], [123.0, 0.0, -53.0, 0.0]),
+    ([2.0, 0.0, 0.0, 0.0, 0.0], [63.0, 0.0, 0.0, 0.0]),
+    ([0.0, 0.0, 1.0, 0.0, 1.0], [63.0, 0.1, -33.0, 0.0]),
+    ([2.0, 0.0, -1.0, 2.0, 2.0], [-59.0, 0.0, 26.0, 0.0]),
+    ([0.0, 0.0, -1.0, 0.0, 1.0], [-58.0, -0.1, 32.0, 0.0]),
+    ([0.0, 0.0, 1.0, 2.0, 1.0], [-51.0, 0.0, 27.0, 0.0]),
+    ([-2.0, 0.0, 2.0, 0.0, 0.0], [48.0, 0.0, 0.0, 0.0]),
+    ([0.0, 0.0, -2.0, 2.0, 1.0], [46.0, 0.0, -24.0, 0.0]),
+    ([2.0, 0.0, 0.0, 2.0, 2.0], [-38.0, 0.0, 16.0, 0.0]),
+    ([0.0, 0.0, 2.0, 2.0, 2.0], [-31.0, 0.0, 13.0, 0.0]),
+    ([0.0, 0.0, 2.0, 0.0, 0.0], [29.0, 0.0, 0.0, 0.0]),
+    ([-2.0, 0.0, 1.0, 2.0, 2.0], [29.0, 0.0, -12.0, 0.0]),
+    ([0.0, 0.0, 0.0, 2.0, 0.0], [26.0, 0.0, 0.0, 0.0]),
+    ([-2.0, 0.0, 0.0, 2.0, 0.0], [-22.0, 0.0, 0.0, 0.0]),
+    ([0.0, 0.0, -1.0, 2.0, 1.0], [21.0, 0.0, -10.0, 0.0]),
+    ([0.0, 2.0, 0.0, 0.0, 0.0], [17.0, -0.1, 0.0, 0.0]),
+    ([2.0, 0.0, -1.0, 0.0, 1.0], [16.0, 0.0, -8.0, 0.0]),
+    ([-2.0, 2.0, 0.0, 2.0, 2.0], [-16.0, 0.1, 7.0, 0.0]),
+];
+
+// Nutation in longitude and obliquity, in radians.
+fn nutation_angles(epoch: f64) -> (f64, f64) {
+    let t = centuries(epoch);
+    let arguments = [
+        297.85036 + 445267.111480 * t - 0.0019142 * t * t + t * t * t / 189474.0,
+        357.52772 + 35999.050340 * t - 0.0001603 * t * t - t * t * t / 300000.0,
+        134.96298 + 477198.867398 * t + 0.0086972 * t * t + t * t * t / 56250.0,
+        93.27191 + 483202.017538 * t - 0.0036825 * t * t + t * t * t / 327270.0,
+        125.04452 - 1934.136261 * t + 0.0020708 * t * t + t * t * t / 450000.0,
+    ];
+
+    let (mut dpsi, mut deps) = (0.0, 0.0);
+    for (multiples, coefficients) in NUTATION.iter() {
+        let angle = multiples.iter().zip(&arguments).map(|(k, x)| k * x).sum::<f64>() * DEG_TO_RAD;
+        dpsi += (coefficients[0] + coefficients[1] * t) * angle.sin();
+        deps += (coefficients[2] + coefficients[3] * t) * angle.cos();
+    }
+    (dpsi * 1e-4 * ARCSEC_TO_RAD, deps * 1e-4 * ARCSEC_TO_RAD)
+}
+
+// From the mean equator and equinox of date to the true ones.
+fn nutation(epoch: f64) -> Matrix3<f64> {
+    let (dpsi, deps) = nutation_angles(epoch);
+    let epsilon = mean_obliquity(epoch);
+    rotate_x(-(epsilon + deps)) * rotate_z(-dpsi) * rotate_x(epsilon)
+}
+
+fn equation_of_equinoxes(epoch: f64) -> f64 {
+    let (dpsi, deps) = nutation_angles(epoch);
+    dpsi * (mean_obliquity(epoch) + deps).cos()
+}
+
+// Greenwich mean sidereal time in radians (IAU 1982).
+fn gmst(epoch: f64) -> f64 {
+    let t = centuries(epoch);
+    let theta = 280.46061837 + 360.98564736629 * (epoch - 2451545.0) + 0.000387933 * t * t - t * t * t / 38710000.0;
+    (theta * DEG_TO_RAD).rem_euclid(2.0 * std::f64::consts::PI)
+}
+
+// Radians per day.
+fn gmst_rate(epoch: f64) -> f64 {
+    let t = centuries(epoch);
+    (360.98564736629 + (2.0 * 0.000387933 * t - 3.0 * t * t / 38710000.0) / 36525.0) * DEG_TO_RAD
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const EPOCH: f64 = 2460000.5;
+
+    // 1987 April 10, 0h, the epoch of Meeus' examples 12.a and 22.a
+    const MEEUS: f64 = 2446895.5;
+
+    fn frames() -> Vec<Frame> {
+        vec![Frame::J2000, Frame::EclipJ2000, Frame::FK4, Frame::Galactic, Frame::Invariable, Frame::MeanEquatorOfDate,
+             Frame::TrueEquatorOfDate, Frame::MeanEclipticOfDate, Frame::TEME, Frame::ITRF]
+    }
+
+    fn arcsec(degrees: f64, minutes: f64, seconds: f64) -> f64 {
+        (degrees * 3600.0 + minutes * 60.0 + seconds) * ARCSEC_TO_RAD
+    }
+
+    #[test]
+    fn built_in_rotations_are_orthogonal() {
+        for frame in frames() {
+            let rotation = frame.rotation(EPOCH).unwrap();
+            assert!((rotation * rotation.transpose() - Matrix3::identity()).abs().max() < 1e-14, "{}", frame);
+            assert!((rotation.determinant() - 1.0).abs() < 1e-14, "{}", frame);
+        }
+    }
+
+    #[test]
+    fn there_and_back_is_the_identity() {
+        for from in frames() {
+            for to in frames() {
+                let forward = from.transformation_to(&to, EPOCH).unwrap();
+                let back = to.transformation_to(&from, EPOCH).unwrap();
+                assert!((back * forward - Matrix6::identity()).abs().max() < 1e-12, "{} -> {} -> {}", from, to, from);
+            }
+        }
+    }
+
+    #[test]
+    fn frames_parse_by_name() {
+        for frame in frames() {
+            assert_eq!(frame.name().parse::<Frame>().unwrap(), frame);
+        }
+        assert_eq!("icrf".parse::<Frame>().unwrap(), Frame::J2000);
+        assert!("nowhere".parse::<Frame>().is_err());
+    }
+
+    #[test]
+    fn registered_frames_parse_by_name() {
+        let rotation = Frame::Galactic.transformation_to(&Frame::EclipJ2000, EPOCH).unwrap().fixed_view::<3, 3>(0, 0).into_owned();
+        let frame = Frame::register("test-galactic-ecliptic", rotation).unwrap();
+        assert_eq!("TEST-GALACTIC-ECLIPTIC".parse::<Frame>().unwrap().rotation(EPOCH).unwrap(), frame.rotation(EPOCH).unwrap());
+        assert!(Frame::register("galactic", rotation).is_none());
+        assert!(Frame::register("test-reflection", -rotation).is_none());
+    }
+
+    #[test]
+    fn nutation_and_obliquity_match_meeus() {
+        // example 22.a: Δψ = -3.788", Δε = +9.443" and ε0 = 23°26'27.407"
+        let (dpsi, deps) = nutation_angles(MEEUS);
+        assert!((dpsi / ARCSEC_TO_RAD + 3.788).abs() < 0.01, "{}", dpsi / ARCSEC_TO_RAD);
+        assert!((deps / ARCSEC_TO_RAD - 9.443).abs() < 0.01, "{}", deps / ARCSEC_TO_RAD);
+        assert!((mean_obliquity(MEEUS) - arcsec(23.0, 26.0, 27.407)).abs() < 0.001 * ARCSEC_TO_RAD);
+    }
+
+    #[test]
+    fn sidereal_time_matches_meeus() {
+        // example 12.a: 13h10m46.3668s at 0h UT, and 12.b: 8h34m57.0896s at 19h21m UT
+        let hours = |h: f64, m: f64, s: f64| arcsec(15.0 * h, 15.0 * m, 15.0 * s);
+        assert!((gmst(MEEUS) - hours(13.0, 10.0, 46.3668)).abs() < 1e-3 * ARCSEC_TO_RAD);
+        assert!((gmst(2446896.30625) - hours(8.0, 34.0, 57.0896)).abs() < 1e-3 * ARCSEC_TO_RAD);
+    }
+
+    #[test]
+    fn teme_lies_between_the_true_equator_and_itrf() {
+        // TEME shares the true pole, with its x axis at the mean equinox: example 12.a gives the equation of
+        // the equinoxes as -0.2317s, between the mean and apparent sidereal times
+        let from_true = Frame::TrueEquatorOfDate.transformation_to(&Frame::TEME, MEEUS).unwrap().fixed_view::<3, 3>(0, 0).into_owned();
+        assert!((from_true - rotate_z(-0.2317 * 15.0 * ARCSEC_TO_RAD)).abs().max() < 0.01 * ARCSEC_TO_RAD);
+
+        // and the earth turns under it by the mean sidereal time, as SGP4 has it
+        let to_itrf = Frame::TEME.transformation_to(&Frame::ITRF, MEEUS).unwrap().fixed_view::<3, 3>(0, 0).into_owned();
+        assert!((to_itrf - rotate_z(gmst(MEEUS))).abs().max() < 1e-12);
+    }
+
+    #[test]
+    fn points_fixed_in_itrf_move_with_the_earth() {
+        // a point on the equator, 6378 km from the centre
+        let position = Vector3::new(4.2635e-5, 0.0, 0.0);
+        let (r, v) = Frame::ITRF.transform(&Frame::J2000, EPOCH, &position, &Vector3::zeros()).unwrap();
+
+        // a step long enough that the julian dates resolve it, and short enough to follow the curve
+        let dt = 5e-4;
+        let (ahead, _) = Frame::ITRF.transform(&Frame::J2000, EPOCH + dt, &position, &Vector3::zeros()).unwrap();
+        let (behind, _) = Frame::ITRF.transform(&Frame::J2000, EPOCH - dt, &position, &Vector3::zeros()).unwrap();
+        assert!(((ahead - behind) / (2.0 * dt) - v).norm() < 1e-5 * v.norm());
+        assert!((v.norm() / r.norm() - gmst_rate(EPOCH)).abs() < 1e-3 * gmst_rate(EPOCH));
+
+        let (back, still) = Frame::J2000.transform(&Frame::ITRF, EPOCH, &r, &v).unwrap();
+        assert!((back - position).norm() < 1e-12 * position.norm());
+        assert!(still.norm() < 1e-12 * v.norm());
+    }
+}
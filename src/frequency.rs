@@ -0,0 +1,267 @@
+use crate::keplerorbit::KeplerOrbit;
+use crate::resonance::ElementHistory;
+use crate::error::{Result, SpaceRocksError};
+
+use nalgebra::{Complex, DMatrix, DVector};
+
+use std::f64::consts::PI;
+
+pub const ARCSEC_PER_YEAR: f64 = 365.25 * 180.0 / PI * 3600.0; // per rad/day
+
+// Secular frequencies of the giant planets in arcsec/yr (Laskar 1990). s5 is zero.
+pub const G5: f64 = 4.257;
+pub const G6: f64 = 28.245;
+pub const G7: f64 = 3.087;
+pub const G8: f64 = 0.673;
+pub const S6: f64 = -26.348;
+pub const S7: f64 = -2.993;
+pub const S8: f64 = -0.692;
+
+// One quasi-periodic term A exp(i(νt + φ)) of a series, with ν in arcsec/yr and the phase at the first epoch.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Term {
+    pub frequency: f64,
+    pub amplitude: f64,
+    pub phase: f64,
+}
+
+impl Term {
+
+    fn value(&self, dt: f64) -> Complex<f64> {
+        polar(self.amplitude, self.frequency / ARCSEC_PER_YEAR * dt + self.phase)
+    }
+}
+
+// e exp(iϖ) along a history.
+pub fn eccentricity_series(history: &ElementHistory) -> Vec<Complex<f64>> {
+    history.orbits.iter().map(|orbit| polar(orbit.e, orbit.node + orbit.arg)).collect()
+}
+
+// sin i exp(iΩ) along a history.
+pub fn inclination_series(history: &ElementHistory) -> Vec<Complex<f64>> {
+    history.orbits.iter().map(|orbit| polar(orbit.inc.sin(), orbit.node)).collect()
+}
+
+// The frequency resolution of a series in arcsec/yr, 2π over its span.
+pub fn resolution(epochs: &[f64]) -> f64 {
+    2.0 * PI / (epochs[epochs.len() - 1] - epochs[0]) * ARCSEC_PER_YEAR
+}
+
+// Numerical analysis of fundamental frequencies (Laskar 1990): repeatedly find the peak of the Hann-windowed Fourier
+// amplitude of the residual within ±max_frequency (arcsec/yr), then fit the amplitudes and phases of every term found
+// so far together by weighted least squares and subtract them. Returns the terms in the order they were found, or an
+// error if the series is too short for the least squares to be solved.
+pub fn naff(epochs: &[f64], series: &[Complex<f64>], terms: usize, max_frequency: f64) -> Result<Vec<Term>> {
+
+    if epochs.len() != series.len() {
+        return Err(SpaceRocksError::InvalidInput(format!("{} epochs but {} samples in the series", epochs.len(), series.len())));
+    }
+    if epochs.len() < 2 || epochs[epochs.len() - 1] <= epochs[0] {
+        return Err(SpaceRocksError::InvalidInput("a series needs at least two increasing epochs".to_string()));
+    }
+
+    let t0 = epochs[0];
+    let span = epochs[epochs.len() - 1] - t0;
+    let weights: Vec<f64> = epochs.iter().map(|t| 1.0 - (2.0 * PI * (t - t0) / span).cos()).collect();
+
+    let mut frequencies: Vec<f64> = Vec::with_capacity(terms);
+    let mut found = Vec::new();
+    let mut residual = series.to_vec();
+
+    for _ in 0..terms {
+        let frequency = peak(epochs, &residual, &weights, max_frequency);
+        if frequencies.iter().any(|f| (f - frequency).abs() < 1e-3 * resolution(epochs)) {
+            break;
+        }
+        frequencies.push(frequency);
+        found = fit(epochs, series, &weights, &frequencies).ok_or_else(|| SpaceRocksError::InvalidInput(
+            format!("can't fit {} terms to a series of {} samples", frequencies.len(), series.len())))?;
+        for (k, t) in epochs.iter().enumerate() {
+            residual[k] = series[k] - found.iter().fold(Complex::new(0.0, 0.0), |sum, term: &Term| sum + term.value(t - t0));
+        }
+    }
+
+    Ok(found)
+}
+
+// The frequency modified Fourier transform (Šidlichovský & Nesvorný 1996): NAFF, corrected for its own bias by
+// analysing the synthetic series built from its terms and removing the difference.
+pub fn fmft(epochs: &[f64], series: &[Complex<f64>], terms: usize, max_frequency: f64) -> Result<Vec<Term>> {
+
+    let first = naff(epochs, series, terms, max_frequency)?;
+    let t0 = epochs[0];
+    let synthetic: Vec<Complex<f64>> = epochs.iter()
+        .map(|t| first.iter().fold(Complex::new(0.0, 0.0), |sum, term| sum + term.value(t - t0)))
+        .collect();
+    let second = naff(epochs, &synthetic, first.len(), max_frequency)?;
+
+    Ok(first.iter().map(|term| {
+        // the nearest term of the synthetic analysis, in case the two came out in a different order
+        let Some(echo) = second.iter().min_by(|a, b| (a.frequency - term.frequency).abs().total_cmp(&(b.frequency - term.frequency).abs())) else {
+            return *term;
+        };
+        let phase = term.phase + (term.phase - echo.phase + PI).rem_euclid(2.0 * PI) - PI;
+        Term {
+            frequency: 2.0 * term.frequency - echo.frequency,
+            amplitude: 2.0 * term.amplitude - echo.amplitude,
+            phase: phase.rem_euclid(2.0 * PI),
+        }
+    }).collect())
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SecularResonance {
+    Nu5,
+    Nu6,
+    Nu7,
+    Nu8,
+    Nu16,
+    Nu17,
+    Nu18,
+}
+
+impl SecularResonance {
+
+    pub const ALL: [SecularResonance; 7] = [SecularResonance::Nu5, SecularResonance::Nu6, SecularResonance::Nu7, SecularResonance::Nu8,
+                                            SecularResonance::Nu16, SecularResonance::Nu17, SecularResonance::Nu18];
+
+    // The planet whose proper mode the resonance is with, as the name of its barycenter.
+    pub fn planet(&self) -> &'static str {
+        match self {
+            SecularResonance::Nu5 => "Jupiter Barycenter",
+            SecularResonance::Nu6 | SecularResonance::Nu16 => "Saturn Barycenter",
+            SecularResonance::Nu7 | SecularResonance::Nu17 => "Uranus Barycenter",
+            SecularResonance::Nu8 | SecularResonance::Nu18 => "Neptune Barycenter",
+        }
+    }
+
+    // g - g_k or s - s_k in arcsec/yr, from the proper frequencies of a rock.
+    pub fn detuning(&self, g: f64, s: f64) -> f64 {
+        match self {
+            SecularResonance::Nu5 => g - G5,
+            SecularResonance::Nu6 => g - G6,
+            SecularResonance::Nu7 => g - G7,
+            SecularResonance::Nu8 => g - G8,
+            SecularResonance::Nu16 => s - S6,
+            SecularResonance::Nu17 => s - S7,
+            SecularResonance::Nu18 => s - S8,
+        }
+    }
+
+    // The resonant angle ϖ - ϖ_k or Ω - Ω_k, using the planet's osculating elements in place of its proper mode,
+    // which dominates them for jupiter and saturn.
+    pub fn angle(&self, rock: &KeplerOrbit, planet: &KeplerOrbit) -> f64 {
+        let angle = match self {
+            SecularResonance::Nu5 | SecularResonance::Nu6 | SecularResonance::Nu7 | SecularResonance::Nu8 => {
+                rock.node + rock.arg - planet.node - planet.arg
+            }
+            _ => rock.node - planet.node,
+        };
+        angle.rem_euclid(2.0 * PI)
+    }
+}
+
+// The secular resonances whose frequencies lie within the tolerance (arcsec/yr) of the rock's g or s,
+// with their detunings, nearest first.
+pub fn secular_resonances(g: f64, s: f64, tolerance: f64) -> Vec<(SecularResonance, f64)> {
+    let mut resonances: Vec<(SecularResonance, f64)> = SecularResonance::ALL.iter()
+        .map(|resonance| (*resonance, resonance.detuning(g, s)))
+        .filter(|(_, detuning)| detuning.abs() < tolerance)
+        .collect();
+    resonances.sort_by(|a, b| a.1.abs().total_cmp(&b.1.abs()));
+    resonances
+}
+
+fn polar(r: f64, theta: f64) -> Complex<f64> {
+    Complex::new(r * theta.cos(), r * theta.sin())
+}
+
+// The windowed Fourier amplitude of a series at a frequency in arcsec/yr.
+fn amplitude(epochs: &[f64], series: &[Complex<f64>], weights: &[f64], frequency: f64) -> f64 {
+    let nu = frequency / ARCSEC_PER_YEAR;
+    let t0 = epochs[0];
+    let sum = epochs.iter().zip(series).zip(weights)
+        .fold(Complex::new(0.0, 0.0), |sum, ((t, z), w)| sum + z * polar(*w, -nu * (t - t0)));
+    sum.norm_sqr().sqrt()
+}
+
+// Scan a grid a quarter of the resolution apart for the largest amplitude, then refine by golden section.
+fn peak(epochs: &[f64], series: &[Complex<f64>], weights: &[f64], max_frequency: f64) -> f64 {
+
+    let step = 0.25 * resolution(epochs);
+    let points = (max_frequency / step).ceil() as i64;
+    let t0 = epochs[0];
+
+    // step every term's phasor along the grid by rotation rather than recomputing it
+    let mut terms: Vec<Complex<f64>> = epochs.iter().zip(series).zip(weights)
+        .map(|((t, z), w)| z * polar(*w, points as f64 * step / ARCSEC_PER_YEAR * (t - t0)))
+        .collect();
+    let rotations: Vec<Complex<f64>> = epochs.iter().map(|t| polar(1.0, -step / ARCSEC_PER_YEAR * (t - t0))).collect();
+
+    let mut best = (0.0, f64::NEG_INFINITY);
+    for k in -points..=points {
+        let sum = terms.iter().fold(Complex::new(0.0, 0.0), |sum, term| sum + term);
+        let value = sum.norm_sqr();
+        if value > best.1 {
+            best = (k as f64 * step, value);
+        }
+        for (term, rotation) in terms.iter_mut().zip(&rotations) {
+            *term *= rotation;
+        }
+    }
+
+    let ratio = 0.5 * (5.0f64.sqrt() - 1.0);
+    let (mut lo, mut hi) = (best.0 - step, best.0 + step);
+    for _ in 0..40 {
+        let x1 = hi - ratio * (hi - lo);
+        let x2 = lo + ratio * (hi - lo);
+        if amplitude(epochs, series, weights, x1) > amplitude(epochs, series, weights, x2) {
+            hi = x2;
+        } else {
+            lo = x1;
+        }
+    }
+
+    0.5 * (lo + hi)
+}
+
+// Weighted least squares for the complex amplitudes of terms at known frequencies, in real and imaginary parts.
+// None if the normal equations are singular.
+fn fit(epochs: &[f64], series: &[Complex<f64>], weights: &[f64], frequencies: &[f64]) -> Option<Vec<Term>> {
+
+    let n = frequencies.len();
+    let t0 = epochs[0];
+    let mut normal = DMatrix::<f64>::zeros(2 * n, 2 * n);
+    let mut rhs = DVector::<f64>::zeros(2 * n);
+
+    for ((t, z), w) in epochs.iter().zip(series).zip(weights) {
+        // the basis exp(iνt) for the real parts and i exp(iνt) for the imaginary parts
+        let basis: Vec<Complex<f64>> = frequencies.iter()
+            .flat_map(|f| {
+                let e = polar(1.0, f / ARCSEC_PER_YEAR * (t - t0));
+                [e, Complex::new(-e.im, e.re)]
+            })
+            .collect();
+        for a in 0..2 * n {
+            rhs[a] += w * (basis[a].re * z.re + basis[a].im * z.im);
+            for b in a..2 * n {
+                normal[(a, b)] += w * (basis[a].re * basis[b].re + basis[a].im * basis[b].im);
+            }
+        }
+    }
+    for a in 0..2 * n {
+        for b in 0..a {
+            normal[(a, b)] = normal[(b, a)];
+        }
+    }
+
+    let solution = normal.lu().solve(&rhs)?;
+    Some(frequencies.iter().enumerate().map(|(j, frequency)| {
+        let c = Complex::new(solution[2 * j], solution[2 * j + 1]);
+        Term {
+            frequency: *frequency,
+            amplitude: c.norm_sqr().sqrt(),
+            phase: c.im.atan2(c.re).rem_euclid(2.0 * PI),
+        }
+    }).collect())
+}
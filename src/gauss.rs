@@ -1,12 +1,19 @@
 use crate::detection::Detection;
 use crate::constants::MU_BARY;
+use crate::error::{Result, SpaceRocksError};
 use crate::keplerorbit::KeplerOrbit;
 use crate::statevector::StateVector;
 
 use nalgebra::Matrix3;
 use nalgebra::matrix;
 
-pub fn gauss(triplet: &Vec<&Detection>, min_distance: f64) -> Option<Vec<KeplerOrbit>> {
+// Preliminary orbits through three detections, one for each root of the distance polynomial beyond
+// min_distance. None if there are no such roots.
+pub fn gauss(triplet: &Vec<&Detection>, min_distance: f64) -> Result<Option<Vec<KeplerOrbit>>> {
+
+    if triplet.len() != 3 {
+        return Err(SpaceRocksError::InvalidInput(format!("gauss needs three detections, got {}", triplet.len())));
+    }
 
     let R1 = triplet[0].observer.position;
     let R2 = triplet[1].observer.position;
@@ -61,7 +68,7 @@ pub fn gauss(triplet: &Vec<&Detection>, min_distance: f64) -> Option<Vec<KeplerO
     // get roots where the imaginary part is 0, and the real part is positive
     let roots: Vec<f64> = complex_roots.iter().filter(|x| x.im == 0.0 && x.re > min_distance).map(|x| x.re).collect();
     if roots.len() == 0 {
-        return None;
+        return Ok(None);
     }
 
     // create an empty vector to hold the eccentricities
@@ -89,6 +96,6 @@ pub fn gauss(triplet: &Vec<&Detection>, min_distance: f64) -> Option<Vec<KeplerO
 
     }
 
-    return Some(res);
+    return Ok(Some(res));
 
 }
\ No newline at end of file
@@ -1 +1,892 @@
+use crate::spacerock::SpaceRock;
+use crate::frame::Frame;
+use crate::error::{Result, SpaceRocksError};
+use crate::forces::{find_sun, ForceModel, PerturberCache};
+use crate::kepler_drift::kepler_drift;
+use crate::mercurius::{integrate_mercurius, Mercurius};
+use crate::events::{find_event, resolve, Event, Found, Handlers};
+use crate::statetransition::StateTransition;
+use crate::encounter::{CloseEncounter, EncounterRadius, EncounterTracker};
 
+use nalgebra::{Matrix3, Matrix6, Matrix6x2, Vector3};
+
+// Adaptive Dormand-Prince 5(4) integrator.
+#[derive(Clone, Copy, Debug)]
+pub struct RK45 {
+    pub timestep: f64, // initial timestep in days
+    pub epsilon: f64,  // tolerance on the local error of each step
+}
+
+impl RK45 {
+
+    pub fn new(timestep: f64, epsilon: f64) -> Self {
+        RK45 {
+            timestep,
+            epsilon,
+        }
+    }
+}
+
+impl Default for RK45 {
+    fn default() -> Self {
+        RK45::new(1.0, 1e-12)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Coordinates {
+    DemocraticHeliocentric, // heliocentric positions and barycentric velocities (Duncan et al. 1998)
+    Jacobi,                 // each body relative to the centre of mass of those interior to it
+}
+
+// Fixed-step Wisdom-Holman mapping for long-term integrations. Symplectic correctors (Wisdom et al. 1996)
+// of order 3, 5 or 7 are available in Jacobi coordinates; order 0 turns them off.
+#[derive(Clone, Copy, Debug)]
+pub struct WHFast {
+    pub timestep: f64, // in days
+    pub coordinates: Coordinates,
+    pub corrector: u8,
+}
+
+impl WHFast {
+
+    pub fn new(timestep: f64, coordinates: Coordinates) -> Self {
+        WHFast {
+            timestep,
+            coordinates,
+            corrector: 0,
+        }
+    }
+
+    pub fn with_corrector(mut self, order: u8) -> Self {
+        self.corrector = order;
+        self
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub enum Integrator {
+    RK45(RK45),
+    WHFast(WHFast),
+    Mercurius(Mercurius),
+}
+
+impl Integrator {
+
+    // Integrate while watching for collisions and ejections. If a handler halts the integration, the rocks
+    // are left at the epoch of the last event.
+    pub fn integrate(&self, rocks: &mut Vec<SpaceRock>, forces: &ForceModel, handlers: &Handlers, epoch: f64) -> Result<Vec<Event>> {
+        match self {
+            Integrator::RK45(integrator) => integrate_with_handlers(rocks, forces, integrator, handlers, epoch),
+            Integrator::WHFast(integrator) => integrate_whfast_with_handlers(rocks, forces, integrator, handlers, epoch),
+            Integrator::Mercurius(integrator) => integrate_mercurius(rocks, forces, integrator, handlers, epoch),
+        }
+    }
+}
+
+impl From<RK45> for Integrator {
+    fn from(integrator: RK45) -> Self {
+        Integrator::RK45(integrator)
+    }
+}
+
+impl From<WHFast> for Integrator {
+    fn from(integrator: WHFast) -> Self {
+        Integrator::WHFast(integrator)
+    }
+}
+
+impl From<Mercurius> for Integrator {
+    fn from(integrator: Mercurius) -> Self {
+        Integrator::Mercurius(integrator)
+    }
+}
+
+// Integrate a set of rocks, which must share an epoch, to a new epoch. The rocks are left in the J2000 frame.
+// Covariances are carried along through the variational equations.
+pub fn integrate(rocks: &mut [SpaceRock], forces: &ForceModel, integrator: &RK45, epoch: f64) -> Result<()> {
+    let with_stm = rocks.iter().any(|rock| rock.covariance.is_some());
+    run(rocks, forces, integrator, epoch, with_stm, None, None)?;
+    Ok(())
+}
+
+// Integrate the rocks along with their first-order variational equations, returning the
+// state transition matrix of each rock from its current epoch to the new one.
+pub fn integrate_with_stm(rocks: &mut [SpaceRock], forces: &ForceModel, integrator: &RK45, epoch: f64) -> Result<Vec<StateTransition>> {
+    Ok(run(rocks, forces, integrator, epoch, true, None, None)?.0)
+}
+
+// Integrate the rocks, recording their close encounters with the massive bodies along the way.
+pub fn integrate_with_encounters(rocks: &mut [SpaceRock], forces: &ForceModel, integrator: &RK45, epoch: f64, radius: EncounterRadius) -> Result<Vec<CloseEncounter>> {
+    let with_stm = rocks.iter().any(|rock| rock.covariance.is_some());
+    let mut tracker = EncounterTracker::new(radius);
+    run(rocks, forces, integrator, epoch, with_stm, Some(&mut tracker), None)?;
+    Ok(tracker.encounters)
+}
+
+// Integrate the rocks, stopping at each collision or ejection to apply its handler.
+pub fn integrate_with_handlers(rocks: &mut Vec<SpaceRock>, forces: &ForceModel, integrator: &RK45, handlers: &Handlers, epoch: f64) -> Result<Vec<Event>> {
+    let mut events = Vec::new();
+    loop {
+        let with_stm = rocks.iter().any(|rock| rock.covariance.is_some());
+        let Some((t, found)) = run(rocks, forces, integrator, epoch, with_stm, None, Some(handlers))?.1 else {
+            return Ok(events);
+        };
+        let (event, _) = resolve(rocks, found, t, handlers);
+        let halt = handlers.halts(&event);
+        events.push(event);
+        if halt {
+            return Ok(events);
+        }
+    }
+}
+
+// The state transition matrices when asked for, and the first event found when there are handlers,
+// in which case the rocks are left at its epoch.
+type Run = (Vec<StateTransition>, Option<(f64, Found)>);
+
+fn run(rocks: &mut [SpaceRock], forces: &ForceModel, integrator: &RK45, epoch: f64, with_stm: bool, mut tracker: Option<&mut EncounterTracker>, handlers: Option<&Handlers>) -> Result<Run> {
+
+    if rocks.is_empty() {
+        return Ok((Vec::new(), None));
+    }
+
+    for rock in rocks.iter_mut() {
+        rock.change_frame(&Frame::J2000)?;
+    }
+
+    let n = rocks.len();
+    let t0 = rocks[0].epoch;
+    forces.check_perturbers(t0, epoch)?;
+    let mut y0 = pack_states(rocks);
+    if with_stm {
+        for _ in 0..n {
+            y0.extend(Matrix6::<f64>::identity().iter());
+            y0.extend(Matrix6x2::<f64>::zeros().iter());
+        }
+    }
+
+    let covariances: Vec<Option<Matrix6<f64>>> = rocks.iter().map(|rock| rock.cartesian_covariance()).collect();
+
+    let mut found = None;
+    let y = {
+        let bodies: &[SpaceRock] = rocks;
+        let perturbers = PerturberCache::new(forces);
+        let on_step = |t: f64, y: &[f64]| {
+            let (positions, velocities) = unpack_vectors(bodies, y);
+            if let Some(tracker) = tracker.as_deref_mut() {
+                tracker.update(bodies, &perturbers.states(t)?, t, &positions, &velocities);
+            }
+            if let Some(handlers) = handlers {
+                found = find_event(bodies, &positions, &velocities, handlers).map(|event| (t, event));
+            }
+            Ok(found.is_none())
+        };
+        if with_stm {
+            dormand_prince(|t, y| Ok(variational_derivatives(bodies, forces, &perturbers.states(t)?, y)), on_step, t0, y0, epoch, integrator)?
+        } else {
+            dormand_prince(|t, y| Ok(derivatives(bodies, forces, &perturbers.states(t)?, y)), on_step, t0, y0, epoch, integrator)?
+        }
+    };
+    unpack_states(rocks, &y, found.map_or(epoch, |(t, _)| t));
+
+    if !with_stm {
+        return Ok((Vec::new(), found));
+    }
+
+    let stms: Vec<StateTransition> = (0..n).map(|i| {
+        let offset = 6 * n + STM_SIZE * i;
+        StateTransition {
+            state: Matrix6::from_column_slice(&y[offset..offset + 36]),
+            nongravs: Matrix6x2::from_column_slice(&y[offset + 36..offset + STM_SIZE]),
+        }
+    }).collect();
+
+    for ((rock, stm), covariance) in rocks.iter_mut().zip(&stms).zip(covariances) {
+        if let Some(covariance) = covariance {
+            rock.set_cartesian_covariance(stm.propagate_covariance(&covariance));
+        }
+    }
+
+    Ok((stms, found))
+}
+
+// Integrate a set of rocks with the Wisdom-Holman map. The sun must be one of the rocks: the other massive rocks
+// orbit it as planets and the massless ones as test particles. Relativity and non-gravitational forces enter as
+// part of the interaction kick, but ephemeris perturbers can't, since they would break the closed system.
+pub fn integrate_whfast(rocks: &mut [SpaceRock], forces: &ForceModel, integrator: &WHFast, epoch: f64) -> Result<()> {
+    let (bodies, order, _) = whfast(rocks, forces, integrator, None, epoch)?;
+    for (body, i) in bodies.into_iter().zip(order) {
+        rocks[i] = body;
+    }
+    Ok(())
+}
+
+// Integrate with the Wisdom-Holman map, checking for collisions and ejections after every step.
+pub fn integrate_whfast_with_handlers(rocks: &mut Vec<SpaceRock>, forces: &ForceModel, integrator: &WHFast, handlers: &Handlers, epoch: f64) -> Result<Vec<Event>> {
+    let (bodies, order, events) = whfast(rocks, forces, integrator, Some(handlers), epoch)?;
+    let mut remaining: Vec<(usize, SpaceRock)> = order.into_iter().zip(bodies).collect();
+    remaining.sort_by_key(|(i, _)| *i);
+    *rocks = remaining.into_iter().map(|(_, rock)| rock).collect();
+    Ok(events)
+}
+
+// The integrated bodies in heliocentric order, with their positions among the rocks.
+fn whfast(rocks: &mut [SpaceRock], forces: &ForceModel, integrator: &WHFast, handlers: Option<&Handlers>, epoch: f64) -> Result<(Vec<SpaceRock>, Vec<usize>, Vec<Event>)> {
+
+    if rocks.is_empty() {
+        return Ok((Vec::new(), Vec::new(), Vec::new()));
+    }
+
+    if !forces.perturbers.is_empty() {
+        return Err(SpaceRocksError::InvalidInput("WHFast can't use ephemeris perturbers; integrate them as rocks instead".to_string()));
+    }
+    if !matches!(integrator.corrector, 0 | 3 | 5 | 7) {
+        return Err(SpaceRocksError::InvalidInput("symplectic correctors are available at order 3, 5 or 7".to_string()));
+    }
+    if integrator.corrector != 0 && integrator.coordinates != Coordinates::Jacobi {
+        return Err(SpaceRocksError::InvalidInput("symplectic correctors need Jacobi coordinates".to_string()));
+    }
+    check_timestep(integrator.timestep)?;
+
+    for rock in rocks.iter_mut() {
+        rock.change_frame(&Frame::J2000)?;
+    }
+
+    let mut order = heliocentric_order(rocks)?;
+    let bodies: Vec<SpaceRock> = order.iter().map(|&i| rocks[i].clone()).collect();
+
+    let t0 = rocks[0].epoch;
+    let span = epoch - t0;
+    let steps = (span.abs() / integrator.timestep.abs()).ceil() as usize;
+    let mut events = Vec::new();
+
+    let mut map = WisdomHolman::new(bodies, forces, integrator.coordinates, t0);
+    if steps == 0 {
+        return Ok((map.into_bodies(epoch), order, events));
+    }
+
+    let h = span / steps as f64;
+    map.correct(integrator.corrector, h, 1.0)?;
+    for _ in 0..steps {
+        map.step(h)?;
+
+        let Some(handlers) = handlers else {
+            continue;
+        };
+        let (positions, velocities) = map.inertial();
+        if find_event(&map.bodies, &positions, &velocities, handlers).is_none() {
+            continue;
+        }
+
+        // events are handled on the real, uncorrected state
+        map.correct(integrator.corrector, h, -1.0)?;
+        let (removed, halt) = map.handle_events(handlers, &mut events);
+        for i in removed {
+            order.remove(i);
+        }
+        if halt {
+            return Ok((map.bodies, order, events));
+        }
+        map.correct(integrator.corrector, h, 1.0)?;
+    }
+    map.correct(integrator.corrector, h, -1.0)?;
+
+    Ok((map.into_bodies(epoch), order, events))
+}
+
+// Indices of the rocks with the sun first, then the massive rocks, then the test particles.
+pub(crate) fn heliocentric_order(rocks: &[SpaceRock]) -> Result<Vec<usize>> {
+    let sun = find_sun(rocks).ok_or_else(|| SpaceRocksError::MissingBody("the sun".to_string()))?;
+    let mut order = vec![sun];
+    order.extend((0..rocks.len()).filter(|&i| i != sun && rocks[i].mass.is_some()));
+    order.extend((0..rocks.len()).filter(|&i| i != sun && rocks[i].mass.is_none()));
+    Ok(order)
+}
+
+// Canonical coordinates of the Wisdom-Holman map, with the barycentre first. Masses are GMs.
+pub(crate) struct WisdomHolman<'a> {
+    pub(crate) bodies: Vec<SpaceRock>,
+    forces: &'a ForceModel,
+    coordinates: Coordinates,
+    pub(crate) masses: Vec<f64>,
+    etas: Vec<f64>, // mass interior to and including each body
+    pub(crate) q: Vec<Vector3<f64>>,
+    pub(crate) p: Vec<Vector3<f64>>,
+    pub(crate) epoch: f64,
+}
+
+impl<'a> WisdomHolman<'a> {
+
+    pub(crate) fn new(bodies: Vec<SpaceRock>, forces: &'a ForceModel, coordinates: Coordinates, epoch: f64) -> Self {
+        let mut map = WisdomHolman {
+            bodies,
+            forces,
+            coordinates,
+            masses: Vec::new(),
+            etas: Vec::new(),
+            q: Vec::new(),
+            p: Vec::new(),
+            epoch,
+        };
+        map.reset();
+        map
+    }
+
+    // Recompute the masses and canonical coordinates from the positions and velocities of the bodies,
+    // after the bodies have been changed.
+    pub(crate) fn reset(&mut self) {
+
+        self.masses = self.bodies.iter().map(|body| body.mass.unwrap_or(0.0)).collect();
+        self.etas = self.masses.iter().scan(0.0, |eta, m| { *eta += m; Some(*eta) }).collect();
+
+        let positions: Vec<Vector3<f64>> = self.bodies.iter().map(|body| body.position).collect();
+        let velocities: Vec<Vector3<f64>> = self.bodies.iter().map(|body| body.velocity).collect();
+
+        let (q, p) = match self.coordinates {
+            Coordinates::Jacobi => (to_jacobi(&self.masses, &positions), to_jacobi(&self.masses, &velocities)),
+            Coordinates::DemocraticHeliocentric => {
+                let total: f64 = self.masses.iter().sum();
+                let com = positions.iter().zip(&self.masses).fold(Vector3::zeros(), |acc, (r, m)| acc + *m * r) / total;
+                let vcom = velocities.iter().zip(&self.masses).fold(Vector3::zeros(), |acc, (v, m)| acc + *m * v) / total;
+                let mut q: Vec<Vector3<f64>> = positions.iter().map(|r| r - positions[0]).collect();
+                let mut p: Vec<Vector3<f64>> = velocities.iter().map(|v| v - vcom).collect();
+                q[0] = com;
+                p[0] = vcom;
+                (q, p)
+            }
+        };
+        self.q = q;
+        self.p = p;
+    }
+
+    // Write the inertial state back into the bodies.
+    pub(crate) fn sync(&mut self) {
+        let (positions, velocities) = self.inertial();
+        for ((body, position), velocity) in self.bodies.iter_mut().zip(positions).zip(velocities) {
+            body.position = position;
+            body.velocity = velocity;
+            body.epoch = self.epoch;
+        }
+    }
+
+    // Apply the handlers to every event in the current state, returning the indices of the bodies removed,
+    // in the order they were removed, and whether the integration should halt. The bodies are left synced.
+    pub(crate) fn handle_events(&mut self, handlers: &Handlers, events: &mut Vec<Event>) -> (Vec<usize>, bool) {
+
+        let (positions, velocities) = self.inertial();
+        if find_event(&self.bodies, &positions, &velocities, handlers).is_none() {
+            return (Vec::new(), false);
+        }
+
+        self.sync();
+        let mut removed = Vec::new();
+        loop {
+            let positions: Vec<Vector3<f64>> = self.bodies.iter().map(|body| body.position).collect();
+            let velocities: Vec<Vector3<f64>> = self.bodies.iter().map(|body| body.velocity).collect();
+            let Some(found) = find_event(&self.bodies, &positions, &velocities, handlers) else {
+                break;
+            };
+            let (event, gone) = resolve(&mut self.bodies, found, self.epoch, handlers);
+            removed.extend(gone);
+            let halt = handlers.halts(&event);
+            events.push(event);
+            if halt {
+                return (removed, true);
+            }
+        }
+        self.reset();
+        (removed, false)
+    }
+
+    pub(crate) fn into_bodies(mut self, epoch: f64) -> Vec<SpaceRock> {
+        self.epoch = epoch;
+        self.sync();
+        self.bodies
+    }
+
+    // Barycentric positions and velocities, in the order of the bodies.
+    pub(crate) fn inertial(&self) -> (Vec<Vector3<f64>>, Vec<Vector3<f64>>) {
+        match self.coordinates {
+            Coordinates::Jacobi => (from_jacobi(&self.masses, &self.q), from_jacobi(&self.masses, &self.p)),
+            Coordinates::DemocraticHeliocentric => {
+                let m0 = self.masses[0];
+                let total: f64 = self.masses.iter().sum();
+                let mut weighted_q = Vector3::zeros();
+                let mut weighted_p = Vector3::zeros();
+                for i in 1..self.q.len() {
+                    weighted_q += self.masses[i] * self.q[i];
+                    weighted_p += self.masses[i] * self.p[i];
+                }
+                let sun_position = self.q[0] - weighted_q / total;
+                let sun_velocity = self.p[0] - weighted_p / m0;
+
+                let mut positions: Vec<Vector3<f64>> = self.q.iter().map(|q| q + sun_position).collect();
+                let mut velocities: Vec<Vector3<f64>> = self.p.iter().map(|p| p + self.p[0]).collect();
+                positions[0] = sun_position;
+                velocities[0] = sun_velocity;
+                (positions, velocities)
+            }
+        }
+    }
+
+    // One kick-drift-kick step, with the drift split in half around the interaction kick.
+    pub(crate) fn step(&mut self, h: f64) -> Result<()> {
+        self.kepler(0.5 * h);
+        self.com(0.5 * h);
+        self.epoch += 0.5 * h;
+        self.jump(0.5 * h);
+        self.kick(h)?;
+        self.jump(0.5 * h);
+        self.kepler(0.5 * h);
+        self.com(0.5 * h);
+        self.epoch += 0.5 * h;
+        Ok(())
+    }
+
+    pub(crate) fn kepler(&mut self, dt: f64) {
+        for i in 1..self.q.len() {
+            let mu = self.kepler_mass(i);
+            let (q, p) = kepler_drift(&self.q[i], &self.p[i], mu, dt);
+            self.q[i] = q;
+            self.p[i] = p;
+        }
+    }
+
+    fn kepler_mass(&self, i: usize) -> f64 {
+        match self.coordinates {
+            Coordinates::Jacobi => self.etas[i],
+            Coordinates::DemocraticHeliocentric => self.masses[0],
+        }
+    }
+
+    pub(crate) fn com(&mut self, dt: f64) {
+        self.q[0] += dt * self.p[0];
+    }
+
+    // The sun's momentum term of the democratic heliocentric Hamiltonian.
+    pub(crate) fn jump(&mut self, dt: f64) {
+        if self.coordinates != Coordinates::DemocraticHeliocentric {
+            return;
+        }
+        let momentum = self.p.iter().zip(&self.masses).skip(1).fold(Vector3::zeros(), |acc, (p, m)| acc + *m * p);
+        let shift = dt * momentum / self.masses[0];
+        for q in self.q.iter_mut().skip(1) {
+            *q += shift;
+        }
+    }
+
+    // Everything but the Keplerian motion: the full force model less the Keplerian part of each orbit.
+    pub(crate) fn kick(&mut self, dt: f64) -> Result<()> {
+        let (positions, velocities) = self.inertial();
+        let accelerations = self.forces.accelerations(&self.bodies, &positions, &velocities, self.epoch)?;
+        let accelerations = match self.coordinates {
+            Coordinates::Jacobi => to_jacobi(&self.masses, &accelerations),
+            Coordinates::DemocraticHeliocentric => accelerations,
+        };
+        for (i, acceleration) in accelerations.iter().enumerate().skip(1) {
+            let mu = self.kepler_mass(i);
+            let r = self.q[i].norm();
+            self.p[i] += dt * (acceleration + mu * self.q[i] / (r * r * r));
+        }
+        Ok(())
+    }
+
+    // Apply the symplectic corrector, or its inverse when inverse is -1.
+    fn correct(&mut self, order: u8, h: f64, inverse: f64) -> Result<()> {
+        let coefficients: &[(f64, f64)] = match order {
+            3 => &CORRECTOR_3,
+            5 => &CORRECTOR_5,
+            7 => &CORRECTOR_7,
+            _ => &[],
+        };
+        for &(a, b) in coefficients {
+            let (a, b) = (a * h, inverse * b * h);
+            self.kepler(a);
+            self.kick(-b)?;
+            self.kepler(-2.0 * a);
+            self.kick(b)?;
+            self.kepler(a);
+        }
+        Ok(())
+    }
+}
+
+// The kernel coefficients are multiples of sqrt(7/40); the b coefficients cancel the first-order
+// error terms of the drift-kick-drift map through h^2, h^4 and h^6.
+const CORRECTOR_A1: f64 = 0.4183300132670378;
+const CORRECTOR_3: [(f64, f64); 2] = [
+    (CORRECTOR_A1, 0.024900596027799867),
+    (-CORRECTOR_A1, -0.024900596027799867),
+];
+const CORRECTOR_5: [(f64, f64); 4] = [
+    (-2.0 * CORRECTOR_A1, 0.008300198675933288),
+    (-CORRECTOR_A1, -0.041500993379666444),
+    (CORRECTOR_A1, 0.041500993379666444),
+    (2.0 * CORRECTOR_A1, -0.008300198675933288),
+];
+const CORRECTOR_7: [(f64, f64); 6] = [
+    (-3.0 * CORRECTOR_A1, -0.00249268114269221),
+    (-2.0 * CORRECTOR_A1, 0.018270923246702132),
+    (-CORRECTOR_A1, -0.053964399093127495),
+    (CORRECTOR_A1, 0.053964399093127495),
+    (2.0 * CORRECTOR_A1, -0.018270923246702132),
+    (3.0 * CORRECTOR_A1, 0.00249268114269221),
+];
+
+// Jacobi coordinates of a set of vectors, with the centre of mass in the first slot.
+fn to_jacobi(masses: &[f64], vectors: &[Vector3<f64>]) -> Vec<Vector3<f64>> {
+    let mut jacobi = vec![Vector3::zeros(); vectors.len()];
+    let mut eta = masses[0];
+    let mut weighted = masses[0] * vectors[0];
+    for i in 1..vectors.len() {
+        jacobi[i] = vectors[i] - weighted / eta;
+        weighted += masses[i] * vectors[i];
+        eta += masses[i];
+    }
+    jacobi[0] = weighted / eta;
+    jacobi
+}
+
+fn from_jacobi(masses: &[f64], jacobi: &[Vector3<f64>]) -> Vec<Vector3<f64>> {
+    let mut vectors = vec![Vector3::zeros(); jacobi.len()];
+    let mut eta: f64 = masses.iter().sum();
+    let mut com = jacobi[0];
+    for i in (1..jacobi.len()).rev() {
+        com -= masses[i] * jacobi[i] / eta;
+        vectors[i] = com + jacobi[i];
+        eta -= masses[i];
+    }
+    vectors[0] = com;
+    vectors
+}
+
+// number of variational entries per rock: the 6x6 state block and the 6x2 non-gravitational block
+const STM_SIZE: usize = 48;
+
+fn variational_derivatives(rocks: &[SpaceRock], forces: &ForceModel, perturbers: &[SpaceRock], y: &[f64]) -> Vec<f64> {
+    let n = rocks.len();
+    let (positions, velocities) = unpack_vectors(rocks, y);
+    let (accelerations, partials) = forces.evaluate(rocks, perturbers, &positions, &velocities, true);
+
+    let mut dydt = Vec::with_capacity(y.len());
+    for i in 0..n {
+        dydt.extend(velocities[i].iter());
+        dydt.extend(accelerations[i].iter());
+    }
+
+    for (i, partials) in partials.iter().enumerate() {
+        let offset = 6 * n + STM_SIZE * i;
+        let phi = Matrix6::from_column_slice(&y[offset..offset + 36]);
+        let psi = Matrix6x2::from_column_slice(&y[offset + 36..offset + STM_SIZE]);
+
+        let mut jacobian = Matrix6::zeros();
+        jacobian.fixed_view_mut::<3, 3>(0, 3).copy_from(&Matrix3::identity());
+        jacobian.fixed_view_mut::<3, 3>(3, 0).copy_from(&partials.position);
+        jacobian.fixed_view_mut::<3, 3>(3, 3).copy_from(&partials.velocity);
+
+        let mut forcing = Matrix6x2::zeros();
+        forcing.fixed_view_mut::<3, 2>(3, 0).copy_from(&partials.nongravs);
+
+        dydt.extend((jacobian * phi).iter());
+        dydt.extend((jacobian * psi + forcing).iter());
+    }
+    dydt
+}
+
+pub(crate) fn pack_states(rocks: &[SpaceRock]) -> Vec<f64> {
+    let mut y = Vec::with_capacity(6 * rocks.len());
+    for rock in rocks {
+        y.extend(rock.position.iter());
+        y.extend(rock.velocity.iter());
+    }
+    y
+}
+
+pub(crate) fn unpack_states(rocks: &mut [SpaceRock], y: &[f64], epoch: f64) {
+    for (i, rock) in rocks.iter_mut().enumerate() {
+        rock.position = Vector3::new(y[6 * i], y[6 * i + 1], y[6 * i + 2]);
+        rock.velocity = Vector3::new(y[6 * i + 3], y[6 * i + 4], y[6 * i + 5]);
+        rock.epoch = epoch;
+    }
+}
+
+pub(crate) fn unpack_vectors(rocks: &[SpaceRock], y: &[f64]) -> (Vec<Vector3<f64>>, Vec<Vector3<f64>>) {
+    let positions = (0..rocks.len()).map(|i| Vector3::new(y[6 * i], y[6 * i + 1], y[6 * i + 2])).collect();
+    let velocities = (0..rocks.len()).map(|i| Vector3::new(y[6 * i + 3], y[6 * i + 4], y[6 * i + 5])).collect();
+    (positions, velocities)
+}
+
+pub(crate) fn derivatives(rocks: &[SpaceRock], forces: &ForceModel, perturbers: &[SpaceRock], y: &[f64]) -> Vec<f64> {
+    let (positions, velocities) = unpack_vectors(rocks, y);
+    let accelerations = forces.evaluate(rocks, perturbers, &positions, &velocities, false).0;
+
+    let mut dydt = Vec::with_capacity(y.len());
+    for i in 0..rocks.len() {
+        dydt.extend(velocities[i].iter());
+        dydt.extend(accelerations[i].iter());
+    }
+    dydt
+}
+
+const C: [f64; 7] = [0.0, 1.0 / 5.0, 3.0 / 10.0, 4.0 / 5.0, 8.0 / 9.0, 1.0, 1.0];
+const A: [[f64; 6]; 7] = [
+    [0.0, 0.0, 0.0, 0.0, 0.0, 0.0],
+    [1.0 / 5.0, 0.0, 0.0, 0.0, 0.0, 0.0],
+    [3.0 / 40.0, 9.0 / 40.0, 0.0, 0.0, 0.0, 0.0],
+    [44.0 / 45.0, -56.0 / 15.0, 32.0 / 9.0, 0.0, 0.0, 0.0],
+    [19372.0 / 6561.0, -25360.0 / 2187.0, 64448.0 / 6561.0, -212.0 / 729.0, 0.0, 0.0],
+    [9017.0 / 3168.0, -355.0 / 33.0, 46732.0 / 5247.0, 49.0 / 176.0, -5103.0 / 18656.0, 0.0],
+    [35.0 / 384.0, 0.0, 500.0 / 1113.0, 125.0 / 192.0, -2187.0 / 6784.0, 11.0 / 84.0],
+];
+const B5: [f64; 7] = [35.0 / 384.0, 0.0, 500.0 / 1113.0, 125.0 / 192.0, -2187.0 / 6784.0, 11.0 / 84.0, 0.0];
+const B4: [f64; 7] = [5179.0 / 57600.0, 0.0, 7571.0 / 16695.0, 393.0 / 640.0, -92097.0 / 339200.0, 187.0 / 2100.0, 1.0 / 40.0];
+
+// the smallest step, in days, the adaptive integrator will shrink to before giving up
+const MIN_TIMESTEP: f64 = 1e-10;
+
+// A timestep of zero would never advance, and one that isn't finite gives no steps at all.
+pub(crate) fn check_timestep(timestep: f64) -> Result<()> {
+    if !timestep.is_finite() || timestep == 0.0 {
+        return Err(SpaceRocksError::InvalidInput(format!("the timestep must be finite and nonzero, not {}", timestep)));
+    }
+    Ok(())
+}
+
+pub(crate) fn check_rk45(integrator: &RK45) -> Result<()> {
+    check_timestep(integrator.timestep)?;
+    if !integrator.epsilon.is_finite() || integrator.epsilon <= 0.0 {
+        return Err(SpaceRocksError::InvalidInput(format!("the tolerance must be finite and positive, not {}", integrator.epsilon)));
+    }
+    Ok(())
+}
+
+// Solve dy/dt = f(t, y) from t0 to t1, calling on_step at the start and after every accepted step.
+// The integration stops early if on_step returns false. Time is tracked relative to t0 so that small
+// steps aren't lost against Julian dates. It fails if f or on_step does, if the derivatives turn NaN,
+// or if the step has to shrink below MIN_TIMESTEP, as at a collision between point masses.
+pub(crate) fn dormand_prince<F, S>(f: F, mut on_step: S, t0: f64, y0: Vec<f64>, t1: f64, integrator: &RK45) -> Result<Vec<f64>>
+where
+    F: Fn(f64, &[f64]) -> Result<Vec<f64>>,
+    S: FnMut(f64, &[f64]) -> Result<bool>,
+{
+    check_rk45(integrator)?;
+    let span = t1 - t0;
+    let direction = if span < 0.0 { -1.0 } else { 1.0 };
+
+    let mut y = y0;
+    let mut s = 0.0;
+    let mut h = direction * integrator.timestep.abs();
+    let mut k: Vec<Vec<f64>> = vec![Vec::new(); 7];
+    let mut y_stage = vec![0.0; y.len()];
+
+    if !on_step(t0, &y)? {
+        return Ok(y);
+    }
+
+    while (span - s) * direction > 0.0 {
+
+        if (s + h - span) * direction > 0.0 {
+            h = span - s;
+        }
+
+        for stage in 0..7 {
+            for i in 0..y.len() {
+                let mut dy = 0.0;
+                for (j, k_j) in k.iter().enumerate().take(stage) {
+                    dy += A[stage][j] * k_j[i];
+                }
+                y_stage[i] = y[i] + h * dy;
+            }
+            // grouped so the last stage falls on exactly the epoch on_step is given for the step
+            k[stage] = f(t0 + (s + C[stage] * h), &y_stage)?;
+        }
+
+        // the last stage was evaluated at the fifth-order solution
+        let mut error = 0.0;
+        for i in 0..y.len() {
+            let mut delta = 0.0;
+            for stage in 0..7 {
+                delta += (B5[stage] - B4[stage]) * k[stage][i];
+            }
+            let scale = integrator.epsilon * (1.0 + y[i].abs().max(y_stage[i].abs()));
+            error += (h * delta / scale).powi(2);
+        }
+        error = (error / y.len() as f64).sqrt();
+        if error.is_nan() {
+            return Err(SpaceRocksError::Integration(format!("the derivatives aren't finite at {}", t0 + s)));
+        }
+
+        if error <= 1.0 {
+            s += h;
+            y.copy_from_slice(&y_stage);
+            if !on_step(t0 + s, &y)? {
+                break;
+            }
+        }
+
+        let factor = if error == 0.0 { 5.0 } else { (0.9 * error.powf(-0.2)).clamp(0.2, 5.0) };
+        h *= factor;
+        if error > 1.0 && h.abs() < MIN_TIMESTEP {
+            return Err(SpaceRocksError::Integration(format!("the step fell below {:e} days at {}", MIN_TIMESTEP, t0 + s)));
+        }
+    }
+
+    Ok(y)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::constants::GM_SUN;
+    use crate::relativity::Relativity;
+    use nalgebra::Vector6;
+
+    const EPOCH: f64 = 2460000.5;
+    const SPAN: f64 = 1000.0;
+
+    // The sun and a test particle on a circular orbit of radius 1 au about it.
+    fn circular() -> Vec<SpaceRock> {
+        let mut sun = SpaceRock::from_xyz("Sun", 0.0, 0.0, 0.0, 0.0, 0.0, 0.0, EPOCH);
+        sun.mass = Some(GM_SUN);
+        let body = SpaceRock::from_xyz("body", 1.0, 0.0, 0.0, 0.0, GM_SUN.sqrt(), 0.0, EPOCH);
+        vec![sun, body]
+    }
+
+    #[test]
+    fn rk45_follows_a_circular_orbit() {
+        let mut rocks = circular();
+        let span = 1000.0;
+        integrate(&mut rocks, &ForceModel::new(), &RK45::new(1.0, 1e-12), EPOCH + span).unwrap();
+        let angle = GM_SUN.sqrt() * span;
+        assert!((rocks[1].position - Vector3::new(angle.cos(), angle.sin(), 0.0)).norm() < 1e-8);
+        assert_eq!(rocks[1].epoch, EPOCH + span);
+    }
+
+    #[test]
+    fn a_zero_timestep_is_rejected() {
+        let result = integrate(&mut circular(), &ForceModel::new(), &RK45::new(0.0, 1e-12), EPOCH + 10.0);
+        assert!(matches!(result, Err(SpaceRocksError::InvalidInput(_))), "{:?}", result);
+    }
+
+    #[test]
+    fn nan_derivatives_stop_the_integration() {
+        let mut rocks = circular();
+        rocks[1].velocity.x = f64::NAN;
+        let result = integrate(&mut rocks, &ForceModel::new(), &RK45::default(), EPOCH + 10.0);
+        assert!(matches!(result, Err(SpaceRocksError::Integration(message)) if message.starts_with("the derivatives aren't finite")));
+    }
+
+    #[test]
+    fn a_plunge_into_a_point_mass_stops_the_integration() {
+        let mut rocks = circular();
+        rocks[1].velocity = Vector3::zeros();
+        let result = integrate(&mut rocks, &ForceModel::new(), &RK45::default(), EPOCH + 1000.0);
+        assert!(matches!(result, Err(SpaceRocksError::Integration(message)) if message.starts_with("the step fell below")));
+    }
+
+    // Compare each column of the state transition matrix against a central difference of the
+    // final state with respect to the matching component of the initial state.
+    fn stm_matches_finite_differences(forces: &ForceModel) {
+        let integrator = RK45::new(1.0, 1e-13);
+        let span = 200.0;
+        let mut start = circular();
+        start[1].position = Vector3::new(0.4, 0.0, 0.05);
+        start[1].velocity = Vector3::new(0.0, 0.025, 0.002);
+
+        let mut rocks = start.clone();
+        let stm = integrate_with_stm(&mut rocks, forces, &integrator, EPOCH + span).unwrap()[1].state;
+
+        let steps = [1e-7, 1e-7, 1e-7, 1e-9, 1e-9, 1e-9];
+        for (j, &step) in steps.iter().enumerate() {
+            let perturbed = |sign: f64| {
+                let mut rocks = start.clone();
+                if j < 3 {
+                    rocks[1].position[j] += sign * step;
+                } else {
+                    rocks[1].velocity[j - 3] += sign * step;
+                }
+                integrate(&mut rocks, forces, &integrator, EPOCH + span).unwrap();
+                Vector6::new(rocks[1].position.x, rocks[1].position.y, rocks[1].position.z,
+                             rocks[1].velocity.x, rocks[1].velocity.y, rocks[1].velocity.z)
+            };
+            let column = (perturbed(1.0) - perturbed(-1.0)) / (2.0 * step);
+            let error = (column - stm.column(j)).norm() / column.norm();
+            assert!(error < 1e-4, "column {} of the stm is off by {:e}", j, error);
+        }
+    }
+
+    #[test]
+    fn stm_matches_finite_differences_newtonian() {
+        stm_matches_finite_differences(&ForceModel::new());
+    }
+
+    #[test]
+    fn stm_matches_finite_differences_with_schwarzschild() {
+        stm_matches_finite_differences(&ForceModel::new().with_relativity(Relativity::Schwarzschild));
+    }
+
+    // The sun and a body on an eccentric, inclined orbit about it, with the body's mass as a fraction of the sun's.
+    fn two_bodies(mass: Option<f64>) -> Vec<SpaceRock> {
+        let mut sun = SpaceRock::from_xyz("Sun", 0.0, 0.0, 0.0, 0.0, 0.0, 0.0, EPOCH);
+        sun.mass = Some(GM_SUN);
+        let mut body = SpaceRock::from_xyz("body", 1.2, 0.1, 0.05, -0.002, 0.019, 0.003, EPOCH);
+        body.mass = mass.map(|mass| mass * GM_SUN);
+        vec![sun, body]
+    }
+
+    // Where the body should be relative to the sun after the span.
+    fn expected(rocks: &[SpaceRock]) -> (Vector3<f64>, Vector3<f64>) {
+        let mu = GM_SUN + rocks[1].mass.unwrap_or(0.0);
+        kepler_drift(&(rocks[1].position - rocks[0].position), &(rocks[1].velocity - rocks[0].velocity), mu, SPAN)
+    }
+
+    fn relative(rocks: &[SpaceRock]) -> (Vector3<f64>, Vector3<f64>) {
+        (rocks[1].position - rocks[0].position, rocks[1].velocity - rocks[0].velocity)
+    }
+
+    #[test]
+    fn rk45_follows_the_kepler_orbit() {
+        for mass in [None, Some(1e-3)] {
+            let mut rocks = two_bodies(mass);
+            let (position, velocity) = expected(&rocks);
+            integrate(&mut rocks, &ForceModel::new(), &RK45::new(1.0, 1e-12), EPOCH + SPAN).unwrap();
+            let (r, v) = relative(&rocks);
+            assert!((r - position).norm() < 1e-8, "{:?}: {:e} au off", mass, (r - position).norm());
+            assert!((v - velocity).norm() < 1e-10);
+            assert_eq!(rocks[1].epoch, EPOCH + SPAN);
+        }
+    }
+
+    // With two bodies the interaction kick vanishes in Jacobi coordinates, as it does for a test particle in either,
+    // so the map is exact whatever the step.
+    #[test]
+    fn whfast_is_exact_for_two_bodies() {
+        let cases = [(None, Coordinates::DemocraticHeliocentric), (None, Coordinates::Jacobi), (Some(1e-3), Coordinates::Jacobi)];
+        for (mass, coordinates) in cases {
+            let mut rocks = two_bodies(mass);
+            let (position, velocity) = expected(&rocks);
+            let corrector = if coordinates == Coordinates::Jacobi { 5 } else { 0 };
+            integrate_whfast(&mut rocks, &ForceModel::new(), &WHFast::new(10.0, coordinates).with_corrector(corrector), EPOCH + SPAN).unwrap();
+            let (r, v) = relative(&rocks);
+            assert!((r - position).norm() < 1e-10, "{:?} {:?}: {:e} au off", mass, coordinates, (r - position).norm());
+            assert!((v - velocity).norm() < 1e-12);
+        }
+    }
+
+    #[test]
+    fn backwards_and_forwards_returns_to_the_start() {
+        let mut rocks = two_bodies(Some(1e-3));
+        let start = relative(&rocks);
+        let integrator = RK45::new(1.0, 1e-12);
+        integrate(&mut rocks, &ForceModel::new(), &integrator, EPOCH - SPAN).unwrap();
+        integrate(&mut rocks, &ForceModel::new(), &integrator, EPOCH).unwrap();
+        assert!((relative(&rocks).0 - start.0).norm() < 1e-8);
+    }
+
+    #[test]
+    fn whfast_rejects_a_zero_timestep() {
+        let result = integrate_whfast(&mut circular(), &ForceModel::new(), &WHFast::new(0.0, Coordinates::Jacobi), EPOCH + 10.0);
+        assert!(matches!(result, Err(SpaceRocksError::InvalidInput(_))), "{:?}", result);
+    }
+}
@@ -0,0 +1,58 @@
+use nalgebra::Vector3;
+
+// Advance a two-body relative state by dt under a central mass mu, using universal variables
+// and Stumpff functions so that elliptic, parabolic and hyperbolic orbits are handled alike.
+pub fn kepler_drift(position: &Vector3<f64>, velocity: &Vector3<f64>, mu: f64, dt: f64) -> (Vector3<f64>, Vector3<f64>) {
+
+    if dt == 0.0 {
+        return (*position, *velocity);
+    }
+
+    let r0 = position.norm();
+    let sqrt_mu = mu.sqrt();
+    let sigma0 = position.dot(velocity) / sqrt_mu;
+    let alpha = 2.0 / r0 - velocity.norm_squared() / mu;
+
+    // Newton's method on the universal Kepler equation, whose derivative is the radius
+    let mut chi = sqrt_mu * dt / r0;
+    for _ in 0..100 {
+        let z = alpha * chi * chi;
+        let (c, s) = stumpff(z);
+        let chi2 = chi * chi;
+        let f = sigma0 * chi2 * c + (1.0 - alpha * r0) * chi2 * chi * s + r0 * chi - sqrt_mu * dt;
+        let r = sigma0 * chi * (1.0 - z * s) + (1.0 - alpha * r0) * chi2 * c + r0;
+        let delta = f / r;
+        chi -= delta;
+        if delta.abs() <= 1e-15 * chi.abs().max(1e-300) {
+            break;
+        }
+    }
+
+    let z = alpha * chi * chi;
+    let (c, s) = stumpff(z);
+    let chi2 = chi * chi;
+    let f = 1.0 - chi2 / r0 * c;
+    let g = dt - chi2 * chi / sqrt_mu * s;
+    let new_position = f * position + g * velocity;
+    let r = new_position.norm();
+    let f_dot = sqrt_mu / (r * r0) * (z * s - 1.0) * chi;
+    let g_dot = 1.0 - chi2 / r * c;
+    let new_velocity = f_dot * position + g_dot * velocity;
+
+    (new_position, new_velocity)
+}
+
+// Stumpff functions c2 and c3, with series expansions near zero.
+fn stumpff(z: f64) -> (f64, f64) {
+    if z.abs() < 1e-4 {
+        let c = 0.5 - z / 24.0 + z * z / 720.0;
+        let s = 1.0 / 6.0 - z / 120.0 + z * z / 5040.0;
+        (c, s)
+    } else if z > 0.0 {
+        let sz = z.sqrt();
+        ((1.0 - sz.cos()) / z, (sz - sz.sin()) / (z * sz))
+    } else {
+        let sz = (-z).sqrt();
+        ((sz.cosh() - 1.0) / -z, (sz.sinh() - sz) / (-z * sz))
+    }
+}
@@ -2,6 +2,8 @@ use crate::constants::MU_BARY;
 use crate::statevector::StateVector;
 use crate::calc_kep_from_xyz;
 
+#[derive(Clone, Copy, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct KeplerOrbit {
     pub a: f64,
     pub e: f64,
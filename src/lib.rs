@@ -1,11 +1,43 @@
 
 pub mod calc_kep_from_xyz;
+pub mod calc_xyz_from_kep;
+pub mod calc_kep_jacobian;
 pub mod keplerorbit;
 pub mod statevector;
 pub mod constants;
+pub mod error;
+pub mod frame;
+pub mod pole;
 pub mod spacerock;
 pub mod observatory;
 pub mod calc_E_from_M;
 pub mod correct_for_ltt;
 pub mod detection;
-pub mod gauss;
\ No newline at end of file
+pub mod gauss;
+pub mod covariance;
+pub mod nongravs;
+pub mod relativity;
+pub mod forces;
+pub mod statetransition;
+pub mod encounter;
+pub mod kepler_drift;
+pub mod events;
+pub mod integrate;
+pub mod mercurius;
+pub mod resonance;
+pub mod classify;
+pub mod frequency;
+pub mod proper;
+pub mod simulation;
+pub mod batch;
+pub mod virtual_impactors;
+pub mod fit_a2;
+pub mod designation;
+pub mod mpcorb;
+// sbdb reads its csv with the csv crate, which comes with the serde feature
+#[cfg(feature = "serde")]
+pub mod sbdb;
+#[cfg(feature = "serde")]
+pub mod catalog;
+#[cfg(feature = "arrow")]
+pub mod rockcollection;
\ No newline at end of file
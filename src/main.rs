@@ -1,54 +1,520 @@
-use spice;
-
 use spacerocks::spacerock::SpaceRock;
 use spacerocks::observatory::Observatory;
-use spacerocks::constants::*;
+use spacerocks::frame::Frame;
+use spacerocks::detection::Detection;
+use spacerocks::constants::PERTURBERS;
+use spacerocks::calc_xyz_from_kep::calc_xyz_from_kep;
+use spacerocks::catalog::{self, Columns};
+use spacerocks::classify::{classify, DynamicalClass};
+use spacerocks::error::{checked, Result, SpaceRocksError};
+use spacerocks::forces::ForceModel;
+use spacerocks::integrate::{integrate, RK45};
+use spacerocks::fit_a2::fit_a2;
+use spacerocks::gauss::gauss;
+use spacerocks::mpcorb::read_mpcorb;
+use spacerocks::sbdb::read_sbdb;
+use spacerocks::batch;
+#[cfg(feature = "arrow")]
+use spacerocks::rockcollection::RockCollection;
+
+use clap::{Args, Parser, Subcommand, ValueEnum};
+use serde::{Deserialize, Serialize};
 
 use std::fs::File;
-use std::io::Write;
+use std::io::{BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+
+#[derive(Parser)]
+#[command(name = "spacerocks", version, about = "Ephemerides, propagation, orbit fitting and catalog conversion for solar system bodies")]
+struct Cli {
+    #[arg(long = "kernel", global = true, help = "A spice kernel to load; may be repeated")]
+    kernels: Vec<PathBuf>,
+    #[arg(long, global = true, help = "A json file listing kernels as {\"kernels\": [...]}, by default ~/.config/spacerocks/config.json")]
+    config: Option<PathBuf>,
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    #[command(about = "Right ascension and declination of spice bodies or catalog rocks from an observer")]
+    Ephem(EphemArgs),
+    #[command(about = "Propagate a catalog to a new epoch")]
+    Propagate(PropagateArgs),
+    #[command(about = "Fit orbits to detections, one per objid")]
+    Fit(FitArgs),
+    #[command(about = "Convert a catalog between formats, frames and origins")]
+    Convert(ConvertArgs),
+    #[command(about = "Classify the rocks of a catalog dynamically")]
+    Classify(ClassifyArgs),
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum InputFormat {
+    Csv,
+    Json,
+    Mpcorb,
+    Sbdb,
+    Parquet,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum OutputFormat {
+    Csv,
+    Json,
+    Parquet,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum ColumnsArg {
+    State,
+    Elements,
+}
+
+#[derive(Args)]
+struct Input {
+    #[arg(help = "The catalog to read")]
+    input: PathBuf,
+    #[arg(long, value_enum, help = "The input format, if not clear from the extension (.csv, .json, .dat, .parquet)")]
+    from: Option<InputFormat>,
+}
+
+#[derive(Args)]
+struct Output {
+    #[arg(short, long, help = "Where to write, rather than standard output")]
+    output: Option<PathBuf>,
+    #[arg(long, value_enum, default_value = "csv", help = "The output format")]
+    to: OutputFormat,
+}
+
+#[derive(Args)]
+struct CatalogOutput {
+    #[command(flatten)]
+    output: Output,
+    #[arg(long, value_enum, default_value = "state", help = "Write states or orbital elements")]
+    columns: ColumnsArg,
+    #[arg(long, help = "The frame to write in, e.g. J2000, ECLIPJ2000 or IAU_JUPITER")]
+    frame: Option<String>,
+    #[arg(long, help = "The spice body to centre on, e.g. SSB or Sun")]
+    origin: Option<String>,
+}
+
+#[derive(Args)]
+struct Site {
+    #[arg(long, requires_all = ["lon", "elevation"], allow_hyphen_values = true, help = "Observatory latitude in degrees; the geocentre if not given")]
+    lat: Option<f64>,
+    #[arg(long, requires = "lat", allow_hyphen_values = true, help = "Observatory longitude in degrees")]
+    lon: Option<f64>,
+    #[arg(long, requires = "lat", help = "Observatory elevation in metres")]
+    elevation: Option<f64>,
+}
+
+#[derive(Args)]
+struct Epochs {
+    #[arg(long, help = "A julian date (UTC); may be repeated")]
+    epoch: Vec<f64>,
+    #[arg(long, requires_all = ["stop", "step"], help = "The first of a range of julian dates (UTC)")]
+    start: Option<f64>,
+    #[arg(long, requires = "start", help = "The end of the range")]
+    stop: Option<f64>,
+    #[arg(long, requires = "start", help = "The spacing of the range in days")]
+    step: Option<f64>,
+}
+
+#[derive(Args)]
+struct EphemArgs {
+    #[arg(long, help = "A spice body, e.g. Moon or \"Jupiter Barycenter\"; may be repeated")]
+    body: Vec<String>,
+    #[arg(long, help = "A catalog of rocks to include")]
+    catalog: Option<PathBuf>,
+    #[arg(long, value_enum, help = "The catalog's format, if not clear from its extension")]
+    from: Option<InputFormat>,
+    #[command(flatten)]
+    epochs: Epochs,
+    #[command(flatten)]
+    site: Site,
+    #[arg(long, help = "Propagate the catalog on two-body orbits rather than integrating")]
+    analytic: bool,
+    #[command(flatten)]
+    output: Output,
+}
+
+#[derive(Args)]
+struct PropagateArgs {
+    #[command(flatten)]
+    input: Input,
+    #[arg(long, help = "The julian date (UTC) to propagate to")]
+    epoch: f64,
+    #[arg(long, help = "Propagate on two-body orbits rather than integrating")]
+    analytic: bool,
+    #[command(flatten)]
+    output: CatalogOutput,
+}
+
+#[derive(Args)]
+struct FitArgs {
+    #[arg(help = "A csv of detections with columns objid, epoch (JD UTC), ra and dec (degrees)")]
+    detections: PathBuf,
+    #[command(flatten)]
+    site: Site,
+    #[arg(long, default_value_t = 0.1, help = "The least distance in au for a preliminary orbit")]
+    min_distance: f64,
+    #[arg(long, help = "Refine the orbit by differential correction, fitting the nongravitational A2 too")]
+    a2: bool,
+    #[arg(long, default_value_t = 0.1, help = "The astrometric uncertainty in arcseconds, for the refined fit")]
+    uncertainty: f64,
+    #[command(flatten)]
+    output: CatalogOutput,
+}
+
+#[derive(Args)]
+struct ConvertArgs {
+    #[command(flatten)]
+    input: Input,
+    #[arg(long, help = "Replace names with their canonical MPC designations where they are designations")]
+    normalise: bool,
+    #[command(flatten)]
+    output: CatalogOutput,
+}
+
+#[derive(Args)]
+struct ClassifyArgs {
+    #[command(flatten)]
+    input: Input,
+    #[command(flatten)]
+    output: Output,
+}
+
+#[derive(Deserialize)]
+struct Config {
+    kernels: Vec<PathBuf>,
+}
+
+#[derive(Serialize)]
+struct EphemRow {
+    name: String,
+    epoch: f64,
+    ra: f64,
+    dec: f64,
+}
+
+#[derive(Deserialize)]
+struct DetectionRow {
+    objid: String,
+    epoch: f64,
+    ra: f64,
+    dec: f64,
+}
+
+#[derive(Serialize)]
+struct ClassRow {
+    name: String,
+    class: String,
+}
+
+fn main() -> ExitCode {
+    let cli = Cli::parse();
+    match run(cli) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(error) => {
+            eprintln!("spacerocks: {}", error);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+fn run(cli: Cli) -> Result<()> {
+    load_kernels(&cli.kernels, cli.config.as_deref())?;
+    match cli.command {
+        Command::Ephem(args) => ephem(args),
+        Command::Propagate(args) => {
+            let mut rocks = read_rocks(&args.input)?;
+            propagate(&mut rocks, args.epoch, args.analytic)?;
+            write_rocks(rocks, &args.output)
+        }
+        Command::Fit(args) => fit(args),
+        Command::Convert(args) => {
+            let mut rocks = read_rocks(&args.input)?;
+            if args.normalise {
+                for rock in rocks.iter_mut() {
+                    // names that aren't designations, like planets', are kept
+                    let _ = rock.normalise_name();
+                }
+            }
+            write_rocks(rocks, &args.output)
+        }
+        Command::Classify(args) => {
+            let rocks = read_rocks(&args.input)?;
+            let rows = rocks.iter().map(|rock| Ok(ClassRow { name: rock.name.clone(), class: label(classify(rock)?) }))
+                            .collect::<Result<Vec<_>>>()?;
+            write_rows(&rows, &args.output)
+        }
+    }
+}
+
+// Kernels from the flags, else from the config file, with relative paths taken from the file's directory.
+fn load_kernels(kernels: &[PathBuf], config: Option<&Path>) -> Result<()> {
+    let default = std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".config/spacerocks/config.json"));
+    let config = match (config, &default) {
+        (Some(config), _) => Some(config.to_path_buf()),
+        (None, Some(default)) if kernels.is_empty() && default.exists() => Some(default.clone()),
+        _ => None,
+    };
+    let mut paths = kernels.to_vec();
+    if let Some(config) = config.filter(|_| kernels.is_empty()) {
+        let parsed: Config = serde_json::from_reader(File::open(&config)?)?;
+        let directory = config.parent().unwrap_or(Path::new("."));
+        paths.extend(parsed.kernels.iter().map(|kernel| directory.join(kernel)));
+    }
+    for path in paths {
+        // a plainer message than cspice's for the commonest mistake; a kernel it can't read is its error
+        if !path.is_file() {
+            return Err(SpaceRocksError::InvalidInput(format!("no kernel at {}", path.display())));
+        }
+        checked(|| spice::furnsh(&path.to_string_lossy()))?;
+    }
+    Ok(())
+}
+
+fn read_rocks(input: &Input) -> Result<Vec<SpaceRock>> {
+    read_catalog(&input.input, input.from)
+}
+
+fn read_catalog(path: &Path, format: Option<InputFormat>) -> Result<Vec<SpaceRock>> {
+    let extension = path.extension().and_then(|extension| extension.to_str()).map(str::to_lowercase);
+    let format = match (format, extension.as_deref()) {
+        (Some(format), _) => format,
+        (None, Some("csv")) => InputFormat::Csv,
+        (None, Some("json")) => InputFormat::Json,
+        (None, Some("dat" | "txt")) => InputFormat::Mpcorb,
+        (None, Some("parquet")) => InputFormat::Parquet,
+        _ => return Err(SpaceRocksError::InvalidInput(format!("can't tell the format of {}; give it with --from", path.display()))),
+    };
+    let file = File::open(path)?;
+    match format {
+        InputFormat::Csv => catalog::read_csv(file),
+        InputFormat::Json => catalog::read_json(file),
+        InputFormat::Mpcorb => read_mpcorb(BufReader::new(file)),
+        InputFormat::Sbdb => read_sbdb(file),
+        #[cfg(feature = "arrow")]
+        InputFormat::Parquet => RockCollection::read_parquet(file)?.to_rocks(),
+        #[cfg(not(feature = "arrow"))]
+        InputFormat::Parquet => Err(SpaceRocksError::InvalidInput("parquet needs the arrow feature".to_string())),
+    }
+}
+
+fn writer(output: &Output) -> Result<Box<dyn Write + Send>> {
+    Ok(match &output.output {
+        Some(path) => Box::new(File::create(path)?),
+        None => Box::new(std::io::stdout()),
+    })
+}
 
+fn write_rocks(mut rocks: Vec<SpaceRock>, catalog: &CatalogOutput) -> Result<()> {
+    if let Some(origin) = &catalog.origin {
+        batch::change_origin(&mut rocks, origin)?;
+    }
+    if let Some(frame) = &catalog.frame {
+        batch::change_frame(&mut rocks, &frame.parse::<Frame>()?)?;
+    }
+    let columns = match catalog.columns {
+        ColumnsArg::State => Columns::State,
+        ColumnsArg::Elements => Columns::Elements,
+    };
+    let writer = writer(&catalog.output)?;
+    match catalog.output.to {
+        OutputFormat::Csv => catalog::write_csv(writer, &rocks, columns),
+        OutputFormat::Json => catalog::write_json(writer, &rocks, columns),
+        #[cfg(feature = "arrow")]
+        OutputFormat::Parquet => RockCollection::from_rocks(&rocks).write_parquet(writer),
+        #[cfg(not(feature = "arrow"))]
+        OutputFormat::Parquet => Err(SpaceRocksError::InvalidInput("parquet needs the arrow feature".to_string())),
+    }
+}
 
-fn main() {
+fn write_rows<T: Serialize>(rows: &[T], output: &Output) -> Result<()> {
+    let writer = writer(output)?;
+    match output.to {
+        OutputFormat::Csv => {
+            let mut writer = csv::Writer::from_writer(writer);
+            for row in rows {
+                writer.serialize(row)?;
+            }
+            writer.flush()?;
+        }
+        OutputFormat::Json => serde_json::to_writer_pretty(writer, rows)?,
+        OutputFormat::Parquet => return Err(SpaceRocksError::InvalidInput("only catalogs can be written as parquet".to_string())),
+    }
+    Ok(())
+}
+
+// Bring the rocks to an epoch, either on two-body orbits or integrated with the planets, one group per
+// starting epoch since an integration needs its rocks to share one. They end up barycentric.
+fn propagate(rocks: &mut [SpaceRock], epoch: f64, analytic: bool) -> Result<()> {
+    batch::change_origin(rocks, "SSB")?;
+    if analytic {
+        return batch::analytic_propagate(rocks, epoch);
+    }
+    let forces = ForceModel::new().with_perturbers(&PERTURBERS)?;
+    let integrator = RK45::default();
+    let mut starts: Vec<f64> = rocks.iter().map(|rock| rock.epoch).filter(|&start| start != epoch).collect();
+    starts.sort_by(f64::total_cmp);
+    starts.dedup();
+    for start in starts {
+        let indices: Vec<usize> = (0..rocks.len()).filter(|&i| rocks[i].epoch == start).collect();
+        let mut group: Vec<SpaceRock> = indices.iter().map(|&i| rocks[i].clone()).collect();
+        integrate(&mut group, &forces, &integrator, epoch)?;
+        for (i, rock) in indices.into_iter().zip(group) {
+            rocks[i] = rock;
+        }
+    }
+    Ok(())
+}
 
-    spice::furnsh("/home/kevin/Desktop/spice/latest_leapseconds.tls");
-    spice::furnsh("/home/kevin/Desktop/spice/de440s.bsp");
+impl Site {
 
-    let mut epochs: Vec<f64> = Vec::new();
-    let mut epoch = 2450000.38373;
-    while epoch < 2450000.38373 + 28.0 {
-        epochs.push(epoch);
-        epoch += 0.01;
+    fn observer(&self, epoch: f64) -> Result<SpaceRock> {
+        match (self.lat, self.lon, self.elevation) {
+            (Some(lat), Some(lon), Some(elevation)) => Observatory::from_coordinates(lat, lon, elevation).at(epoch),
+            _ => SpaceRock::from_spice("Earth", epoch),
+        }
     }
+}
 
-    let mut file = File::create("/home/kevin/Desktop/positions.csv").unwrap();
-    file.write_all(b"objid,x,y,z\n").unwrap();
-    let objids = &vec!["Jupiter Barycenter", "Earth", "Sun", "Neptune Barycenter", "Mars Barycenter", 
-                      "Venus Barycenter", "Mercury Barycenter", "Saturn Barycenter", "Uranus Barycenter", "Pluto Barycenter", "Moon"];
-    for epoch in &epochs {
-        for objid in objids {
-            let mut body = SpaceRock::from_spice(objid, *epoch);
-            body.change_frame("ECLIPJ2000");
-            file.write_all(format!("{objid}, {x}, {y}, {z}\n", 
-                                   objid=body.name, 
-                                   x=body.position.x, 
-                                   y=body.position.y, 
-                                   z=body.position.z).as_bytes()).unwrap();
+impl Epochs {
+
+    fn list(&self) -> Result<Vec<f64>> {
+        let mut epochs = self.epoch.clone();
+        if let (Some(start), Some(stop), Some(step)) = (self.start, self.stop, self.step) {
+            if step <= 0.0 {
+                return Err(SpaceRocksError::InvalidInput("the step must be positive".to_string()));
+            }
+            if stop < start {
+                return Err(SpaceRocksError::InvalidInput("the stop must not be before the start".to_string()));
+            }
+            let n = ((stop - start) / step).floor() as usize;
+            epochs.extend((0..=n).map(|k| start + k as f64 * step));
+        }
+        if epochs.is_empty() {
+            return Err(SpaceRocksError::InvalidInput("give an --epoch or a --start, --stop and --step".to_string()));
         }
+        epochs.sort_by(f64::total_cmp);
+        Ok(epochs)
     }
+}
 
-    let w84 = Observatory::from_coordinates(-30.00293494202556, -70.80642, 2207.0);
-    let mut file = File::create("/home/kevin/Desktop/moon-sky.csv").unwrap();
-    file.write_all(b"objid,epoch,ra,dec\n").unwrap();
-    for epoch in &epochs {
-        let mut body = SpaceRock::from_spice("moon", *epoch);
-        println!("Epoch: {}", *epoch);
-        let observer = w84.at(*epoch);
-        let [ra, dec] = body.observe(&observer);
-        file.write_all(format!("{objid}, {epoch}, {ra}, {dec}\n", 
-                               objid=body.name, 
-                               epoch=body.epoch, 
-                               ra=ra.to_degrees(), 
-                               dec=dec.to_degrees()).as_bytes()).unwrap();
+fn ephem(args: EphemArgs) -> Result<()> {
+    let epochs = args.epochs.list()?;
+    let mut rocks = match &args.catalog {
+        Some(path) => read_catalog(path, args.from)?,
+        None => Vec::new(),
+    };
+    if rocks.is_empty() && args.body.is_empty() {
+        return Err(SpaceRocksError::InvalidInput("give a --body or a --catalog".to_string()));
+    }
+
+    let mut rows = Vec::new();
+    for &epoch in &epochs {
+        let observer = args.site.observer(epoch)?;
+        let mut bodies = args.body.iter().map(|name| SpaceRock::from_spice(name, epoch)).collect::<Result<Vec<_>>>()?;
+        propagate(&mut rocks, epoch, args.analytic)?;
+        for rocks in [&mut bodies, &mut rocks] {
+            let positions = batch::observe(rocks, &observer)?;
+            rows.extend(rocks.iter().zip(positions).map(|(rock, [ra, dec])| EphemRow {
+                name: rock.name.clone(),
+                epoch,
+                ra: ra.to_degrees().rem_euclid(360.0),
+                dec: dec.to_degrees(),
+            }));
         }
+    }
+    write_rows(&rows, &args.output)
+}
+
+fn fit(args: FitArgs) -> Result<()> {
+    let mut reader = csv::ReaderBuilder::new().trim(csv::Trim::All).from_reader(File::open(&args.detections)?);
+    let mut rows: Vec<DetectionRow> = reader.deserialize().collect::<std::result::Result<_, _>>()?;
+    rows.sort_by(|a, b| a.objid.cmp(&b.objid).then(a.epoch.total_cmp(&b.epoch)));
 
-}
\ No newline at end of file
+    let mut fitted = Vec::new();
+    for group in rows.chunk_by(|a, b| a.objid == b.objid) {
+        let objid = &group[0].objid;
+        let detections = group.iter().map(|row| {
+            Ok(Detection::new(row.ra, row.dec, 0.0, 0.0, row.epoch, row.objid.clone(), String::new(), args.site.observer(row.epoch)?))
+        }).collect::<Result<Vec<_>>>()?;
+        let detections: Vec<&Detection> = detections.iter().collect();
+
+        match fit_one(objid, &detections, &args)? {
+            Some((rock, rms)) => {
+                eprintln!("{}: {} detections, rms {:.3}\"", objid, detections.len(), rms);
+                fitted.push(rock);
+            }
+            None => eprintln!("{}: no orbit found", objid),
+        }
+    }
+    write_rocks(fitted, &args.output)
+}
+
+// A preliminary orbit through the first, middle and last detections, choosing among Gauss's roots by the
+// residuals of the whole arc, then refined if asked. Returns the rock with its rms residual in arcseconds.
+fn fit_one(objid: &str, detections: &[&Detection], args: &FitArgs) -> Result<Option<(SpaceRock, f64)>> {
+    if detections.len() < 3 {
+        return Ok(None);
+    }
+    let middle = detections.len() / 2;
+    let triplet = vec![detections[0], detections[middle], detections[detections.len() - 1]];
+    let Some(orbits) = gauss(&triplet, args.min_distance)? else {
+        return Ok(None);
+    };
+
+    let mut best: Option<(SpaceRock, f64)> = None;
+    for orbit in orbits {
+        let rock = SpaceRock::from_state(objid, calc_xyz_from_kep(orbit), detections[middle].epoch);
+        let rms = rms_residual(&rock, detections)?;
+        if rms.is_finite() && best.as_ref().is_none_or(|(_, best)| rms < *best) {
+            best = Some((rock, rms));
+        }
+    }
+    let Some((rock, rms)) = best else {
+        return Ok(None);
+    };
+    if !args.a2 {
+        return Ok(Some((rock, rms)));
+    }
+
+    let forces = ForceModel::new().with_perturbers(&PERTURBERS)?;
+    let uncertainty = (args.uncertainty / 3600.0).to_radians();
+    match fit_a2(&rock, &[], detections, &forces, &RK45::default(), uncertainty)? {
+        Some((refined, sigma)) => {
+            let a2 = refined.nongravs.map_or(0.0, |nongravs| nongravs.a2);
+            eprintln!("{}: A2 = {:e} ± {:e} au/day^2", objid, a2, sigma);
+            let rms = rms_residual(&refined, detections)?;
+            Ok(Some((refined, rms)))
+        }
+        None => {
+            eprintln!("{}: the refined fit didn't converge, keeping the preliminary orbit", objid);
+            Ok(Some((rock, rms)))
+        }
+    }
+}
+
+fn rms_residual(rock: &SpaceRock, detections: &[&Detection]) -> Result<f64> {
+    let mut sum = 0.0;
+    for detection in detections {
+        let mut rock = rock.clone();
+        rock.analytic_propagate(detection.epoch)?;
+        let [ra, dec] = rock.observe(&detection.observer)?;
+        let d_ra = (detection.ra.to_radians() - ra + std::f64::consts::PI).rem_euclid(2.0 * std::f64::consts::PI) - std::f64::consts::PI;
+        let d_dec = detection.dec.to_radians() - dec;
+        sum += (d_ra * dec.cos()).powi(2) + d_dec.powi(2);
+    }
+    Ok((sum / detections.len() as f64).sqrt().to_degrees() * 3600.0)
+}
+
+fn label(class: DynamicalClass) -> String {
+    match class {
+        DynamicalClass::Resonant { p, q } => format!("Resonant {}:{}", p, q),
+        class => format!("{:?}", class),
+    }
+}
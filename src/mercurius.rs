@@ -0,0 +1,438 @@
+use crate::spacerock::SpaceRock;
+use crate::frame::Frame;
+use crate::error::{Result, SpaceRocksError};
+use crate::forces::ForceModel;
+use crate::integrate::{check_rk45, check_timestep, dormand_prince, heliocentric_order, Coordinates, WisdomHolman, RK45};
+use crate::kepler_drift::kepler_drift;
+use crate::encounter::{find_closest_approach, hermite};
+use crate::events::{resolve, CollisionHandler, Event, Found, Handlers};
+
+use nalgebra::Vector3;
+
+// Hybrid symplectic integrator after MERCURY (Chambers 1999) and MERCURIUS (Rein et al. 2019). Interactions
+// between bodies are split by a smooth changeover function: the far part is kicked as in the Wisdom-Holman
+// map, while bodies inside the changeover radius of one another have their close part integrated with RK45.
+#[derive(Clone, Copy, Debug)]
+pub struct Mercurius {
+    pub timestep: f64,   // in days
+    pub changeover: f64, // changeover radius in Hill radii
+    pub encounter: RK45, // integrator for close encounters
+}
+
+impl Mercurius {
+
+    pub fn new(timestep: f64) -> Self {
+        Mercurius {
+            timestep,
+            changeover: 3.0,
+            encounter: RK45::default(),
+        }
+    }
+
+    pub fn with_changeover(mut self, changeover: f64) -> Self {
+        self.changeover = changeover;
+        self
+    }
+
+    pub fn with_encounter_integrator(mut self, encounter: RK45) -> Self {
+        self.encounter = encounter;
+        self
+    }
+}
+
+// Integrate a set of rocks with the hybrid scheme. As with WHFast, the sun must be one of the rocks. Collisions,
+// including those of rocks that pass within the sun's radius, are found during close encounters, and ejections
+// at the end of each step; the remaining rocks keep their order.
+pub fn integrate_mercurius(rocks: &mut Vec<SpaceRock>, forces: &ForceModel, integrator: &Mercurius, handlers: &Handlers, epoch: f64) -> Result<Vec<Event>> {
+
+    if rocks.is_empty() {
+        return Ok(Vec::new());
+    }
+
+    if !forces.perturbers.is_empty() {
+        return Err(SpaceRocksError::InvalidInput("MERCURIUS can't use ephemeris perturbers; integrate them as rocks instead".to_string()));
+    }
+    check_timestep(integrator.timestep)?;
+    check_rk45(&integrator.encounter)?;
+
+    for rock in rocks.iter_mut() {
+        rock.change_frame(&Frame::J2000)?;
+    }
+
+    let order = heliocentric_order(rocks)?;
+    let bodies: Vec<SpaceRock> = order.iter().map(|&i| rocks[i].clone()).collect();
+
+    let t0 = rocks[0].epoch;
+    let span = epoch - t0;
+    let steps = (span.abs() / integrator.timestep.abs()).ceil() as usize;
+    let h = if steps > 0 { span / steps as f64 } else { 0.0 };
+
+    let mut hybrid = Hybrid::new(WisdomHolman::new(bodies, forces, Coordinates::DemocraticHeliocentric, t0), order, integrator, handlers, h);
+    for _ in 0..steps {
+        hybrid.step(h)?;
+        if hybrid.halted {
+            break;
+        }
+    }
+
+    let events = hybrid.events;
+    let bodies = if hybrid.halted { hybrid.map.bodies } else { hybrid.map.into_bodies(epoch) };
+    let mut remaining: Vec<(usize, SpaceRock)> = hybrid.indices.into_iter().zip(bodies).collect();
+    remaining.sort_by_key(|(i, _)| *i);
+    *rocks = remaining.into_iter().map(|(_, rock)| rock).collect();
+
+    Ok(events)
+}
+
+struct Hybrid<'a> {
+    map: WisdomHolman<'a>,
+    indices: Vec<usize>, // position of each body in the caller's rocks
+    critical: Vec<f64>,  // changeover radius of each body
+    integrator: &'a Mercurius,
+    handlers: &'a Handlers,
+    events: Vec<Event>,
+    halted: bool,
+}
+
+impl<'a> Hybrid<'a> {
+
+    fn new(map: WisdomHolman<'a>, indices: Vec<usize>, integrator: &'a Mercurius, handlers: &'a Handlers, h: f64) -> Self {
+        let mut hybrid = Hybrid {
+            map,
+            indices,
+            critical: Vec::new(),
+            integrator,
+            handlers,
+            events: Vec::new(),
+            halted: false,
+        };
+        hybrid.critical = hybrid.critical_radii(h);
+        hybrid
+    }
+
+    // The larger of the scaled Hill radius, the distance covered in a fraction of a step on a circular
+    // orbit, and the physical radius. They are fixed at the start so that the splitting stays symplectic.
+    fn critical_radii(&self, h: f64) -> Vec<f64> {
+        let m0 = self.map.masses[0];
+        (0..self.map.bodies.len()).map(|i| {
+            if i == 0 {
+                return 0.0;
+            }
+            let r = self.map.q[i].norm();
+            let hill = r * (self.map.masses[i] / (3.0 * m0)).cbrt();
+            let circular = 0.4 * (m0 / r).sqrt() * h.abs();
+            let radius = 1.21 * self.map.bodies[i].radius.unwrap_or(0.0);
+            (self.integrator.changeover * hill).max(circular).max(radius)
+        }).collect()
+    }
+
+    fn step(&mut self, h: f64) -> Result<()> {
+        self.kick(0.5 * h)?;
+        self.map.jump(0.5 * h);
+        self.map.com(h);
+        self.drift(h)?;
+        if self.halted {
+            return Ok(());
+        }
+        self.map.jump(0.5 * h);
+        self.map.epoch += h;
+        self.kick(0.5 * h)?;
+
+        let (removed, halt) = self.map.handle_events(self.handlers, &mut self.events);
+        for i in removed {
+            self.indices.remove(i);
+            self.critical.remove(i);
+        }
+        self.halted = halt;
+        Ok(())
+    }
+
+    // Pairs that can interact: at least one of the two must be massive.
+    fn pairs(&self) -> Vec<(usize, usize)> {
+        let n = self.map.bodies.len();
+        let mut pairs = Vec::new();
+        for i in 1..n {
+            for j in i + 1..n {
+                if self.map.masses[i] > 0.0 || self.map.masses[j] > 0.0 {
+                    pairs.push((i, j));
+                }
+            }
+        }
+        pairs
+    }
+
+    // The far part of the interactions: the full kick less the close part of every pair inside its changeover radius.
+    fn kick(&mut self, dt: f64) -> Result<()> {
+        self.map.kick(dt)?;
+        for (i, j) in self.pairs() {
+            let d = self.map.q[i] - self.map.q[j];
+            let r = d.norm();
+            let rcrit = self.critical[i].max(self.critical[j]);
+            if r < rcrit {
+                let close = (1.0 - changeover(r, rcrit)) * d / (r * r * r);
+                self.map.p[i] += dt * self.map.masses[j] * close;
+                self.map.p[j] -= dt * self.map.masses[i] * close;
+            }
+        }
+        Ok(())
+    }
+
+    // The Keplerian motion plus the close part of the interactions. Bodies that come within the changeover
+    // radius of one another during the step are integrated together; the rest follow their Kepler orbits.
+    fn drift(&mut self, h: f64) -> Result<()> {
+
+        let n = self.map.bodies.len();
+        let m0 = self.map.masses[0];
+        let predicted: Vec<(Vector3<f64>, Vector3<f64>)> = (0..n).map(|i| {
+            if i == 0 {
+                (self.map.q[0], self.map.p[0])
+            } else {
+                kepler_drift(&self.map.q[i], &self.map.p[i], m0, h)
+            }
+        }).collect();
+
+        let mut encountering = vec![false; n];
+        for (i, j) in self.pairs() {
+            let r0 = self.map.q[i] - self.map.q[j];
+            let v0 = self.map.p[i] - self.map.p[j];
+            let r1 = predicted[i].0 - predicted[j].0;
+            let v1 = predicted[i].1 - predicted[j].1;
+            let mut rmin = r0.norm().min(r1.norm());
+            if r0.dot(&v0) * h.signum() < 0.0 && r1.dot(&v1) * h.signum() > 0.0 {
+                let tau = find_closest_approach(&r0, &v0, &r1, &v1, h);
+                rmin = rmin.min(hermite(&r0, &v0, &r1, &v1, h, tau).0.norm());
+            }
+            if rmin < 1.1 * self.critical[i].max(self.critical[j]) {
+                encountering[i] = true;
+                encountering[j] = true;
+            }
+        }
+
+        // rocks whose Kepler orbits pass within the sun's radius during the step are integrated too, to find the collision
+        for i in 1..n {
+            if let Some(radius) = self.sun_contact(i) {
+                let (r1, v1) = predicted[i];
+                if closest_to_sun(&self.map.q[i], &self.map.p[i], &r1, &v1, m0, h) < 1.1 * radius {
+                    encountering[i] = true;
+                }
+            }
+        }
+
+        let mut subset: Vec<usize> = (1..n).filter(|&i| encountering[i]).collect();
+        let mut y = Vec::with_capacity(6 * subset.len());
+        for &i in &subset {
+            y.extend(self.map.q[i].iter());
+            y.extend(self.map.p[i].iter());
+        }
+
+        let start: Vec<(Vector3<f64>, Vector3<f64>)> = self.map.q.iter().copied().zip(self.map.p.iter().copied()).collect();
+        for i in (1..n).filter(|&i| !encountering[i]) {
+            (self.map.q[i], self.map.p[i]) = predicted[i];
+        }
+
+        let mut t = 0.0;
+        while !subset.is_empty() {
+            let mut collision = None;
+            let mut previous: Option<(f64, Vec<f64>)> = None;
+            y = dormand_prince(
+                |_, y| Ok(self.encounter_derivatives(&subset, y)),
+                |s, y| {
+                    let last = previous.as_ref().map(|(t, y)| (s - t, y.as_slice()));
+                    collision = self.find_collision(&subset, last, y).map(|pair| (s, pair));
+                    previous = Some((s, y.to_vec()));
+                    Ok(collision.is_none())
+                },
+                t, y, h, &self.integrator.encounter,
+            )?;
+
+            for (k, &i) in subset.iter().enumerate() {
+                self.map.q[i] = Vector3::new(y[6 * k], y[6 * k + 1], y[6 * k + 2]);
+                self.map.p[i] = Vector3::new(y[6 * k + 3], y[6 * k + 4], y[6 * k + 5]);
+            }
+
+            let Some((s, (i, j))) = collision else {
+                break;
+            };
+
+            if self.handlers.collision == CollisionHandler::Halt {
+                // stop everything at the collision: bring the rest of the bodies back to its time
+                for i in (1..n).filter(|&i| !encountering[i]) {
+                    (self.map.q[i], self.map.p[i]) = kepler_drift(&start[i].0, &start[i].1, m0, s);
+                }
+                self.map.q[0] -= (h - s) * self.map.p[0];
+                self.map.epoch += s;
+                self.map.sync();
+                let (event, _) = resolve(&mut self.map.bodies, Found::Collision(i, j), self.map.epoch, self.handlers);
+                self.events.push(event);
+                self.halted = true;
+                return Ok(());
+            }
+
+            self.map.sync();
+            let (event, removed) = resolve(&mut self.map.bodies, Found::Collision(i, j), self.map.epoch + s, self.handlers);
+            self.events.push(event);
+            for &r in &removed {
+                self.indices.remove(r);
+                self.critical.remove(r);
+                if let Some(k) = subset.iter().position(|&index| index == r) {
+                    subset.remove(k);
+                }
+                for index in subset.iter_mut() {
+                    if *index > r {
+                        *index -= 1;
+                    }
+                }
+            }
+            self.map.reset();
+            t = s;
+
+            // the states of the pair changed in the collision
+            y.truncate(6 * subset.len());
+            for (k, &i) in subset.iter().enumerate() {
+                y[6 * k..6 * k + 3].copy_from_slice(self.map.q[i].as_slice());
+                y[6 * k + 3..6 * k + 6].copy_from_slice(self.map.p[i].as_slice());
+            }
+        }
+        Ok(())
+    }
+
+    fn encounter_derivatives(&self, subset: &[usize], y: &[f64]) -> Vec<f64> {
+        let m0 = self.map.masses[0];
+        let q: Vec<Vector3<f64>> = (0..subset.len()).map(|k| Vector3::new(y[6 * k], y[6 * k + 1], y[6 * k + 2])).collect();
+
+        let mut dydt = Vec::with_capacity(y.len());
+        for (a, &i) in subset.iter().enumerate() {
+            let r = q[a].norm();
+            let mut acc = -m0 * q[a] / (r * r * r);
+            for (b, &j) in subset.iter().enumerate() {
+                if a == b || self.map.masses[j] == 0.0 {
+                    continue;
+                }
+                let d = q[a] - q[b];
+                let r = d.norm();
+                let rcrit = self.critical[i].max(self.critical[j]);
+                acc -= (1.0 - changeover(r, rcrit)) * self.map.masses[j] * d / (r * r * r);
+            }
+            dydt.extend(y[6 * a + 3..6 * a + 6].iter());
+            dydt.extend(acc.iter());
+        }
+        dydt
+    }
+
+    // The sun's radius plus a rock's, if both are known.
+    fn sun_contact(&self, i: usize) -> Option<f64> {
+        Some(self.map.bodies[0].radius? + self.map.bodies[i].radius?)
+    }
+
+    // A pair of bodies that touch at the end of an accepted encounter step, or a rock that passed within the sun's
+    // radius during it. The sun is index 0; last holds the length of the step and the states at its start.
+    fn find_collision(&self, subset: &[usize], last: Option<(f64, &[f64])>, y: &[f64]) -> Option<(usize, usize)> {
+        let state = |y: &[f64], k: usize| (Vector3::new(y[6 * k], y[6 * k + 1], y[6 * k + 2]), Vector3::new(y[6 * k + 3], y[6 * k + 4], y[6 * k + 5]));
+        for (a, &i) in subset.iter().enumerate() {
+            let Some(radius) = self.sun_contact(i) else {
+                continue;
+            };
+            let (r1, v1) = state(y, a);
+            let mut rmin = if r1.dot(&v1) < 0.0 { r1.norm() } else { f64::INFINITY };
+            if let Some((dt, y0)) = last {
+                let (r0, v0) = state(y0, a);
+                if dt != 0.0 && r0.dot(&v0) * dt.signum() < 0.0 && r1.dot(&v1) * dt.signum() >= 0.0 {
+                    let tau = find_closest_approach(&r0, &v0, &r1, &v1, dt);
+                    rmin = rmin.min(hermite(&r0, &v0, &r1, &v1, dt, tau).0.norm());
+                }
+            }
+            if rmin < radius {
+                return Some((0, i));
+            }
+        }
+
+        for (a, &i) in subset.iter().enumerate() {
+            for (b, &j) in subset.iter().enumerate().skip(a + 1) {
+                if self.map.masses[i] == 0.0 && self.map.masses[j] == 0.0 {
+                    continue;
+                }
+                let (Some(ri), Some(rj)) = (self.map.bodies[i].radius, self.map.bodies[j].radius) else {
+                    continue;
+                };
+                let d = Vector3::new(y[6 * a] - y[6 * b], y[6 * a + 1] - y[6 * b + 1], y[6 * a + 2] - y[6 * b + 2]);
+                let v = Vector3::new(y[6 * a + 3] - y[6 * b + 3], y[6 * a + 4] - y[6 * b + 4], y[6 * a + 5] - y[6 * b + 5]);
+                if d.norm() < ri + rj && d.dot(&v) < 0.0 {
+                    return Some((i, j));
+                }
+            }
+        }
+        None
+    }
+}
+
+// The least heliocentric distance over a step on a Kepler orbit, from its states at either end. Passing
+// pericentre gives its distance, |h|^2 / (mu (1 + e)).
+fn closest_to_sun(r0: &Vector3<f64>, v0: &Vector3<f64>, r1: &Vector3<f64>, v1: &Vector3<f64>, mu: f64, dt: f64) -> f64 {
+    let mut rmin = r0.norm().min(r1.norm());
+    if r0.dot(v0) * dt.signum() < 0.0 && r1.dot(v1) * dt.signum() >= 0.0 {
+        let h = r0.cross(v0);
+        let e = (v0.cross(&h) / mu - r0 / r0.norm()).norm();
+        rmin = rmin.min(h.norm_squared() / (mu * (1.0 + e)));
+    }
+    rmin
+}
+
+// The smooth switch of Chambers (1999): zero inside a tenth of the changeover radius and one outside it.
+fn changeover(r: f64, rcrit: f64) -> f64 {
+    let y = (r - 0.1 * rcrit) / (0.9 * rcrit);
+    if y <= 0.0 {
+        0.0
+    } else if y >= 1.0 {
+        1.0
+    } else {
+        y * y * y * (10.0 - 15.0 * y + 6.0 * y * y)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::constants::GM_SUN;
+
+    const EPOCH: f64 = 2460000.5;
+    const SUN_RADIUS: f64 = 0.00465; // au
+
+    // The sun and a test particle falling from 1 au on an orbit with the given pericentre distance.
+    fn falling(pericentre: f64) -> Vec<SpaceRock> {
+        let mut sun = SpaceRock::from_xyz("Sun", 0.0, 0.0, 0.0, 0.0, 0.0, 0.0, EPOCH);
+        sun.mass = Some(GM_SUN);
+        sun.radius = Some(SUN_RADIUS);
+        // the angular momentum of an orbit with apocentre 1 au and this pericentre
+        let h = (2.0 * GM_SUN * pericentre / (1.0 + pericentre)).sqrt();
+        let mut body = SpaceRock::from_xyz("body", 1.0, 0.0, 0.0, 0.0, h, 0.0, EPOCH);
+        body.radius = Some(1e-9);
+        vec![sun, body]
+    }
+
+    #[test]
+    fn a_plunging_orbit_collides_with_the_sun() {
+        for handler in [CollisionHandler::Merge, CollisionHandler::Remove] {
+            let mut rocks = falling(0.001);
+            let handlers = Handlers::new().with_collision(handler);
+            let events = integrate_mercurius(&mut rocks, &ForceModel::new(), &Mercurius::new(4.0), &handlers, EPOCH + 100.0).unwrap();
+            assert_eq!(events.len(), 1);
+            let Event::Collision { epoch, rocks: pair } = &events[0] else {
+                panic!("expected a collision, not {:?}", events[0]);
+            };
+            assert_eq!(pair, &("Sun".to_string(), "body".to_string()));
+            // the free-fall time from 1 au, nearly half of the orbital period of 0.5 au
+            let fall = std::f64::consts::PI * (0.125 / GM_SUN).sqrt();
+            assert!((epoch - EPOCH - fall).abs() < 0.5, "collided {} days in, not {}", epoch - EPOCH, fall);
+            assert_eq!(rocks.len(), 1);
+            assert_eq!(rocks[0].mass, Some(GM_SUN));
+        }
+    }
+
+    #[test]
+    fn a_grazing_orbit_misses_the_sun() {
+        let mut rocks = falling(1.5 * SUN_RADIUS);
+        let events = integrate_mercurius(&mut rocks, &ForceModel::new(), &Mercurius::new(4.0), &Handlers::new(), EPOCH + 100.0).unwrap();
+        assert!(events.is_empty());
+        assert_eq!(rocks.len(), 2);
+    }
+}
@@ -0,0 +1,180 @@
+use crate::spacerock::SpaceRock;
+use crate::keplerorbit::KeplerOrbit;
+use crate::calc_E_from_M::calc_f_from_M;
+use crate::constants::{DEG_TO_RAD, SECONDS_PER_DAY};
+use crate::error::{checked, Result, SpaceRocksError};
+use crate::designation;
+
+use std::io::BufRead;
+
+// Read the MPC's orbit catalogue (MPCORB.DAT, or any extract in its fixed-width format, e.g. NEA.txt).
+// Each orbit becomes a barycentric rock in ecliptic J2000, named by its readable designation (unpacked from
+// the packed one where that column is empty) and with its H and G. The header, up to the row of dashes, is
+// skipped. Needs the leapseconds and an ephemeris loaded.
+pub fn read_mpcorb<R: BufRead>(reader: R) -> Result<Vec<SpaceRock>> {
+    read_orbits(reader)?.iter().map(CatalogOrbit::to_rock).collect()
+}
+
+// An orbit as the catalogues give it: osculating heliocentric ecliptic elements at an epoch in TT.
+#[derive(Clone, Debug)]
+pub(crate) struct CatalogOrbit {
+    pub name: String,
+    pub epoch: f64,
+    pub orbit: KeplerOrbit,
+    pub h: Option<f64>,
+    pub g: Option<f64>,
+}
+
+impl CatalogOrbit {
+    pub fn to_rock(&self) -> Result<SpaceRock> {
+        let mut rock = SpaceRock::from_heliocentric_elements(&self.name, self.orbit, utc_from_tdb(self.epoch)?)?;
+        rock.h = self.h;
+        rock.g = self.g;
+        Ok(rock)
+    }
+}
+
+fn read_orbits<R: BufRead>(reader: R) -> Result<Vec<CatalogOrbit>> {
+    let mut orbits = Vec::new();
+    let mut started = false;
+    for (number, line) in reader.lines().enumerate() {
+        let line = line?;
+        if line.starts_with("-----") {
+            started = true;
+            continue;
+        }
+        if line.trim().is_empty() {
+            continue;
+        }
+        match parse_line(&line) {
+            Ok(orbit) => {
+                started = true;
+                orbits.push(orbit);
+            }
+            Err(_) if !started => continue,
+            Err(message) => return Err(SpaceRocksError::Parse(format!("line {} of MPCORB: {}", number + 1, message))),
+        }
+    }
+    Ok(orbits)
+}
+
+fn parse_line(line: &str) -> std::result::Result<CatalogOrbit, String> {
+    let field = |start: usize, end: usize| line.get(start - 1..end.min(line.len())).unwrap_or("").trim();
+    let number = |start: usize, end: usize, what: &str| -> std::result::Result<f64, String> {
+        field(start, end).parse::<f64>().map_err(|_| format!("bad {} '{}'", what, field(start, end)))
+    };
+    let optional = |start: usize, end: usize| field(start, end).parse::<f64>().ok();
+
+    let epoch = unpack_epoch(field(21, 25))?;
+    let mean_anomaly = number(27, 35, "mean anomaly")? * DEG_TO_RAD;
+    let arg = number(38, 46, "argument of perihelion")? * DEG_TO_RAD;
+    let node = number(49, 57, "longitude of the node")? * DEG_TO_RAD;
+    let inc = number(60, 68, "inclination")? * DEG_TO_RAD;
+    let e = number(71, 79, "eccentricity")?;
+    let a = number(93, 103, "semimajor axis")?;
+
+    let name = match field(167, 194) {
+        "" => designation::unpack(field(1, 7)).unwrap_or_else(|_| field(1, 7).to_string()),
+        name => name.to_string(),
+    };
+
+    Ok(CatalogOrbit {
+        name,
+        epoch,
+        orbit: KeplerOrbit::new(a, e, inc, arg, node, calc_f_from_M(e, mean_anomaly)),
+        h: optional(9, 13),
+        g: optional(15, 19),
+    })
+}
+
+// A packed epoch, e.g. K2555 for 2025 May 5.0, as a julian date (TT).
+pub fn unpack_epoch(packed: &str) -> std::result::Result<f64, String> {
+    let digit = |c: char| match c {
+        '1'..='9' => Some(c as i32 - '0' as i32),
+        'A'..='V' => Some(c as i32 - 'A' as i32 + 10),
+        _ => None,
+    };
+    let chars: Vec<char> = packed.chars().collect();
+    let (century, year, month, day) = match chars[..] {
+        [c @ 'I'..='L', tens, ones, m, d] => (c as i32 - 'A' as i32 + 10, tens.to_digit(10).zip(ones.to_digit(10)), digit(m), digit(d)),
+        _ => return Err(format!("bad packed epoch '{}'", packed)),
+    };
+    match (year, month, day) {
+        (Some((tens, ones)), Some(month), Some(day)) if month <= 12 => {
+            Ok(julian_date(100 * century + 10 * tens as i32 + ones as i32, month, day))
+        }
+        _ => Err(format!("bad packed epoch '{}'", packed)),
+    }
+}
+
+// The julian date at 0h of a gregorian calendar date.
+fn julian_date(year: i32, month: i32, day: i32) -> f64 {
+    let (year, month) = if month <= 2 { (year - 1, month + 12) } else { (year, month) };
+    let century = year.div_euclid(100);
+    let gregorian = 2 - century + century.div_euclid(4);
+    (365.25 * (year + 4716) as f64).floor() + (30.6001 * (month + 1) as f64).floor() + day as f64 + gregorian as f64 - 1524.5
+}
+
+// Catalogues give epochs in TT (or TDB, the same to within 2 ms) while rocks are kept in UTC.
+pub(crate) fn utc_from_tdb(epoch: f64) -> Result<f64> {
+    let et = (epoch - 2451545.0) * SECONDS_PER_DAY;
+    let delta = checked(|| spice::deltet(et, "ET"))?;
+    Ok(epoch - delta / SECONDS_PER_DAY)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CERES: &str = "00001    3.34  0.15 K2555 188.70269   73.27343   80.25221   10.58780  0.0794013  0.21424651   2.7660512  0 E2024-V47  7330 125 1801-2024 0.80 M-v 30k MPCLINUX   4000 (1) Ceres                   20241101";
+
+    #[test]
+    fn packed_epochs_unpack() {
+        assert_eq!(unpack_epoch("K2555"), Ok(2460800.5));
+        assert_eq!(unpack_epoch("J9611"), Ok(2450083.5));
+        assert!(unpack_epoch("K25D5").is_err());
+        assert!(unpack_epoch("K255").is_err());
+        assert!(unpack_epoch("M2555").is_err());
+    }
+
+    #[test]
+    fn julian_dates_match_meeus() {
+        assert_eq!(julian_date(2000, 1, 1), 2451544.5);
+        assert_eq!(julian_date(1957, 10, 4), 2436115.5);
+        assert_eq!(julian_date(1600, 1, 1), 2305447.5);
+        assert_eq!(julian_date(1987, 6, 19), 2446965.5);
+    }
+
+    #[test]
+    fn fields_are_read_from_their_columns() {
+        let orbit = parse_line(CERES).unwrap();
+        assert_eq!(orbit.name, "(1) Ceres");
+        assert_eq!(orbit.epoch, 2460800.5);
+        assert_eq!((orbit.h, orbit.g), (Some(3.34), Some(0.15)));
+        assert_eq!(orbit.orbit.a, 2.7660512);
+        assert_eq!(orbit.orbit.e, 0.0794013);
+        assert_eq!(orbit.orbit.inc, 10.58780 * DEG_TO_RAD);
+        assert_eq!(orbit.orbit.node, 80.25221 * DEG_TO_RAD);
+        assert_eq!(orbit.orbit.arg, 73.27343 * DEG_TO_RAD);
+        assert_eq!(orbit.orbit.f, calc_f_from_M(0.0794013, 188.70269 * DEG_TO_RAD));
+    }
+
+    #[test]
+    fn the_header_is_skipped_and_bad_lines_are_errors() {
+        let catalogue = format!("MINOR PLANET CENTER ORBIT DATABASE (MPCORB)\n\n-----------------\n{}\n\n", CERES);
+        let orbits = read_orbits(catalogue.as_bytes()).unwrap();
+        assert_eq!(orbits.len(), 1);
+        assert_eq!(orbits[0].name, "(1) Ceres");
+
+
+        let broken = format!("{}\n{}", CERES, CERES.replace("0.0794013", "0.07x4013"));
+        assert!(matches!(read_orbits(broken.as_bytes()), Err(SpaceRocksError::Parse(message)) if message.starts_with("line 2")));
+    }
+
+    #[test]
+    fn an_empty_readable_name_is_unpacked() {
+        assert_eq!(parse_line(&CERES[..166]).unwrap().name, "1");
+        let provisional = format!("K07Tf8A{}", &CERES[7..166]);
+        assert_eq!(parse_line(&provisional).unwrap().name, "2007 TA418");
+    }
+}
@@ -0,0 +1,75 @@
+use crate::constants::*;
+
+use nalgebra::{Matrix3x2, Vector3};
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct NonGravs {
+    pub a2: f64,           // transverse (Yarkovsky) acceleration at 1 au, in au/day^2
+    pub area_to_mass: f64, // effective area-to-mass ratio for radiation pressure, in m^2/kg
+}
+
+impl NonGravs {
+
+    pub fn new(a2: f64, area_to_mass: f64) -> Self {
+        NonGravs {
+            a2,
+            area_to_mass,
+        }
+    }
+
+    pub fn yarkovsky(a2: f64) -> Self {
+        NonGravs::new(a2, 0.0)
+    }
+
+    pub fn radiation_pressure(area_to_mass: f64) -> Self {
+        NonGravs::new(0.0, area_to_mass)
+    }
+
+    // Build the transverse term from an orbit-averaged semimajor axis drift (au/day)
+    // for an orbit with semimajor axis a (au) and eccentricity e.
+    pub fn from_dadt(dadt: f64, a: f64, e: f64) -> Self {
+        let n = (MU_BARY / (a * a * a)).sqrt();
+        NonGravs::yarkovsky(0.5 * dadt * n * a * a * (1.0 - e * e))
+    }
+
+    // The orbit-averaged semimajor axis drift (au/day) produced by the transverse term,
+    // assuming the 1/r^2 scaling used in the force model.
+    pub fn dadt(&self, a: f64, e: f64) -> f64 {
+        let n = (MU_BARY / (a * a * a)).sqrt();
+        2.0 * self.a2 / (n * a * a * (1.0 - e * e))
+    }
+
+    // Acceleration for a body at heliocentric position and velocity (au, au/day).
+    pub fn acceleration(&self, position: &Vector3<f64>, velocity: &Vector3<f64>) -> Vector3<f64> {
+        let r = position.norm();
+        let r_hat = position / r;
+        let g = 1.0 / (r * r);
+
+        let mut acc = Vector3::zeros();
+
+        if self.a2 != 0.0 {
+            let h = position.cross(velocity);
+            let t_hat = h.cross(position).normalize();
+            acc += self.a2 * g * t_hat;
+        }
+
+        if self.area_to_mass != 0.0 {
+            acc += SOLAR_PRESSURE_1AU * self.area_to_mass * g * r_hat;
+        }
+
+        acc
+    }
+
+    // Partial derivatives of the acceleration with respect to (a2, area_to_mass).
+    pub fn partials(position: &Vector3<f64>, velocity: &Vector3<f64>) -> Matrix3x2<f64> {
+        let r = position.norm();
+        let g = 1.0 / (r * r);
+        let t_hat = position.cross(velocity).cross(position).normalize();
+        let r_hat = position / r;
+        let mut partials = Matrix3x2::zeros();
+        partials.set_column(0, &(g * t_hat));
+        partials.set_column(1, &(SOLAR_PRESSURE_1AU * g * r_hat));
+        partials
+    }
+}
@@ -1,5 +1,6 @@
 use crate::spacerock::SpaceRock;
 use crate::constants::*;
+use crate::error::Result;
 
 use spice;
 use nalgebra::Vector3;
@@ -20,15 +21,15 @@ impl Observatory {
         }
     }
 
-    pub fn at(&self, epoch: f64) -> SpaceRock {
-        let mut earth = SpaceRock::from_spice("Earth", epoch);
+    pub fn at(&self, epoch: f64) -> Result<SpaceRock> {
+        let mut earth = SpaceRock::from_spice("Earth", epoch)?;
 
         // compute the topocentric correction to the position of the observatory using the local sidereal time
         let [d_pos, d_vel] = compute_topocentric_correction(self.lon, self.lat, self.elevation, epoch);
         earth.position += d_pos;
         earth.velocity += d_vel;
         
-        return earth
+        return Ok(earth)
     }
 }
 
@@ -0,0 +1,53 @@
+use crate::constants::DEG_TO_RAD;
+
+// An IAU rotation model: right ascension and declination of the north pole in degrees, with rates per
+// julian century, and the prime meridian W in degrees, with its rate per day, all from J2000. Neptune's
+// pole also swings around with the angle N, whose terms are in degrees and degrees per century.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Pole {
+    pub body: &'static str,
+    pub ra: [f64; 2],
+    pub dec: [f64; 2],
+    pub w: [f64; 2],
+    pub nodal: [f64; 5], // N at J2000 and its rate, then the amplitudes of sin N in ra, cos N in dec and sin N in W
+}
+
+// Archinal et al. (2011), the 2009 report of the IAU working group, without the small periodic terms.
+pub const POLES: [Pole; 10] = [
+    Pole { body: "Sun", ra: [286.13, 0.0], dec: [63.87, 0.0], w: [84.176, 14.1844000], nodal: [0.0; 5] },
+    Pole { body: "Mercury", ra: [281.0097, -0.0328], dec: [61.4143, -0.0049], w: [329.5469, 6.1385025], nodal: [0.0; 5] },
+    Pole { body: "Venus", ra: [272.76, 0.0], dec: [67.16, 0.0], w: [160.20, -1.4813688], nodal: [0.0; 5] },
+    Pole { body: "Earth", ra: [0.0, -0.641], dec: [90.0, -0.557], w: [190.147, 360.9856235], nodal: [0.0; 5] },
+    Pole { body: "Mars", ra: [317.68143, -0.1061], dec: [52.88650, -0.0609], w: [176.630, 350.89198226], nodal: [0.0; 5] },
+    Pole { body: "Jupiter", ra: [268.056595, -0.006499], dec: [64.495303, 0.002413], w: [284.95, 870.5360000], nodal: [0.0; 5] },
+    Pole { body: "Saturn", ra: [40.589, -0.036], dec: [83.537, -0.004], w: [38.90, 810.7939024], nodal: [0.0; 5] },
+    Pole { body: "Uranus", ra: [257.311, 0.0], dec: [-15.175, 0.0], w: [203.81, -501.1600928], nodal: [0.0; 5] },
+    Pole { body: "Neptune", ra: [299.36, 0.0], dec: [43.46, 0.0], w: [249.978, 541.1397757], nodal: [357.85, 52.316, 0.70, -0.51, -0.48] },
+    Pole { body: "Pluto", ra: [132.993, 0.0], dec: [-6.163, 0.0], w: [302.695, 56.3625225], nodal: [0.0; 5] },
+];
+
+impl Pole {
+
+    // The model for a body by name, ignoring case and a trailing "barycenter".
+    pub fn of(name: &str) -> Option<Pole> {
+        let name = name.trim();
+        let name = name.strip_suffix(" barycenter").or_else(|| name.strip_suffix(" Barycenter")).unwrap_or(name);
+        POLES.iter().find(|pole| pole.body.eq_ignore_ascii_case(name)).copied()
+    }
+
+    // Right ascension and declination of the pole and the prime meridian, in radians, at a julian date (TDB).
+    pub fn orientation(&self, epoch: f64) -> (f64, f64, f64) {
+        let d = epoch - 2451545.0;
+        let t = d / 36525.0;
+        let n = ((self.nodal[0] + self.nodal[1] * t) * DEG_TO_RAD).sin_cos();
+        let ra = self.ra[0] + self.ra[1] * t + self.nodal[2] * n.0;
+        let dec = self.dec[0] + self.dec[1] * t + self.nodal[3] * n.1;
+        let w = self.w[0] + self.w[1] * d + self.nodal[4] * n.0;
+        (ra * DEG_TO_RAD, dec * DEG_TO_RAD, (w * DEG_TO_RAD).rem_euclid(2.0 * std::f64::consts::PI))
+    }
+
+    // The spin rate in radians per day.
+    pub fn spin_rate(&self) -> f64 {
+        self.w[1] * DEG_TO_RAD
+    }
+}
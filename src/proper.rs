@@ -0,0 +1,136 @@
+use crate::spacerock::SpaceRock;
+use crate::forces::ForceModel;
+use crate::integrate::Integrator;
+use crate::frame::Frame;
+use crate::error::Result;
+use crate::resonance::{ElementHistory, check_window};
+use crate::frequency::{fmft, resolution, eccentricity_series, inclination_series, G5, G6, S6};
+use crate::constants::*;
+
+use nalgebra::Complex;
+
+use std::ops::{AddAssign, Div, SubAssign};
+
+// The fastest secular frequency considered, in arcsec/yr.
+const MAX_FREQUENCY: f64 = 200.0;
+
+// How many terms to take out of each series before picking the free one.
+const TERMS: usize = 6;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ProperElements {
+    pub a: f64,     // au
+    pub e: f64,
+    pub sin_i: f64,
+    pub g: f64,     // precession rate of the perihelion, arcsec/yr
+    pub s: f64,     // precession rate of the node, arcsec/yr
+}
+
+impl ProperElements {
+
+    // Synthetic proper elements (Knežević & Milani 2000) from an element history spanning many secular periods, best
+    // taken relative to the invariable plane. The elements are smoothed with a running mean over the window (days) to
+    // take out the short-period terms; then a is the mean, and e and sin i are the amplitudes of the largest free terms
+    // of e exp(iϖ) and sin i exp(iΩ), whose frequencies are g and s.
+    pub fn from_history(history: &ElementHistory, window: f64) -> Result<Self> {
+
+        history.check()?;
+        check_window(window)?;
+
+        let epochs = &history.epochs;
+        let a: Vec<f64> = history.orbits.iter().map(|orbit| orbit.a).collect();
+        let a = running_mean(epochs, &a, window);
+        let eccentricity = running_mean(epochs, &eccentricity_series(history), window);
+        let inclination = running_mean(epochs, &inclination_series(history), window);
+
+        // the forced terms from jupiter and saturn, and the reference plane for the inclination
+        let (g, e) = free_term(epochs, &eccentricity, &[G5, G6])?;
+        let (s, sin_i) = free_term(epochs, &inclination, &[0.0, S6])?;
+
+        Ok(ProperElements {
+            a: a.iter().sum::<f64>() / a.len() as f64,
+            e,
+            sin_i,
+            g,
+            s,
+        })
+    }
+
+    // Integrate the rock through the epochs along with the bodies (the planets, or none if they are perturbers
+    // in the force model) and compute its proper elements in the invariable plane. None if the rock was lost
+    // along the way.
+    pub fn from_integration(rock: &SpaceRock, bodies: &[SpaceRock], forces: &ForceModel, integrator: &Integrator, epochs: &[f64], window: f64) -> Result<Option<Self>> {
+        let mut rocks = bodies.to_vec();
+        rocks.push(rock.clone());
+        let histories = ElementHistory::record(&mut rocks, forces, integrator, epochs, &Frame::Invariable)?;
+        histories.iter()
+            .find(|history| history.name == rock.name)
+            .map(|history| ProperElements::from_history(history, window))
+            .transpose()
+    }
+}
+
+// The distance between two sets of proper elements in m/s under the standard metric of Zappalà et al. (1990).
+pub fn hcm_distance(first: &ProperElements, second: &ProperElements) -> f64 {
+    let a = 0.5 * (first.a + second.a);
+    let na = (GM_SUN / a).sqrt() / (M_TO_AU * SECONDS_PER_DAY);
+    let da = (first.a - second.a) / a;
+    let de = first.e - second.e;
+    let di = first.sin_i - second.sin_i;
+    na * (1.25 * da * da + 2.0 * de * de + 2.0 * di * di).sqrt()
+}
+
+// The hierarchical clustering method: the family of the seed is everything reachable from it through a chain
+// of neighbours closer than the cutoff velocity (m/s). Returns indices into the elements, starting with the seed.
+pub fn hcm_family(elements: &[ProperElements], seed: usize, cutoff: f64) -> Vec<usize> {
+
+    let mut members = vec![seed];
+    let mut joined = vec![false; elements.len()];
+    joined[seed] = true;
+
+    let mut k = 0;
+    while k < members.len() {
+        let current = members[k];
+        for (i, other) in elements.iter().enumerate() {
+            if !joined[i] && hcm_distance(&elements[current], other) < cutoff {
+                joined[i] = true;
+                members.push(i);
+            }
+        }
+        k += 1;
+    }
+
+    members
+}
+
+// The largest term away from the forced frequencies, as its frequency and amplitude.
+fn free_term(epochs: &[f64], series: &[Complex<f64>], forced: &[f64]) -> Result<(f64, f64)> {
+    let tolerance = 2.0 * resolution(epochs);
+    Ok(fmft(epochs, series, TERMS, MAX_FREQUENCY)?.iter()
+        .filter(|term| forced.iter().all(|f| (term.frequency - f).abs() > tolerance))
+        .max_by(|a, b| a.amplitude.total_cmp(&b.amplitude))
+        .map_or((0.0, 0.0), |term| (term.frequency, term.amplitude)))
+}
+
+// A centred running mean over a window in days, for uneven sampling.
+fn running_mean<T>(epochs: &[f64], values: &[T], window: f64) -> Vec<T>
+    where T: Copy + Default + AddAssign + SubAssign + Div<f64, Output = T> {
+
+    let (mut lo, mut hi) = (0, 0);
+    let mut sum = T::default();
+    let mut means = Vec::with_capacity(values.len());
+
+    for epoch in epochs {
+        while hi < values.len() && epochs[hi] - epoch <= 0.5 * window {
+            sum += values[hi];
+            hi += 1;
+        }
+        while epoch - epochs[lo] > 0.5 * window {
+            sum -= values[lo];
+            lo += 1;
+        }
+        means.push(sum / (hi - lo) as f64);
+    }
+
+    means
+}
@@ -0,0 +1,105 @@
+use crate::constants::SPEED_OF_LIGHT;
+
+use nalgebra::Vector3;
+
+const C2: f64 = SPEED_OF_LIGHT * SPEED_OF_LIGHT;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum Relativity {
+    #[default]
+    Newtonian,
+    Schwarzschild, // the sun's field only, applied to every rock
+    EIH,           // Einstein-Infeld-Hoffmann equations for all massive bodies
+}
+
+// Post-Newtonian correction for a body at a position and velocity relative to a mass gm.
+pub fn schwarzschild_correction(gm: f64, position: &Vector3<f64>, velocity: &Vector3<f64>) -> Vector3<f64> {
+    let r = position.norm();
+    let v_squared = velocity.dot(velocity);
+    let r_dot_v = position.dot(velocity);
+    gm / (C2 * r * r * r) * ((4.0 * gm / r - v_squared) * position + 4.0 * r_dot_v * velocity)
+}
+
+// Post-Newtonian corrections (beta = gamma = 1) to the accelerations of every body, given
+// the Newtonian accelerations. Only bodies with a mass act as sources.
+pub fn eih_corrections(masses: &[Option<f64>], positions: &[Vector3<f64>], velocities: &[Vector3<f64>], newtonian: &[Vector3<f64>]) -> Vec<Vector3<f64>> {
+
+    let n = masses.len();
+    let massive: Vec<(usize, f64)> = masses.iter().enumerate().filter_map(|(j, mass)| mass.map(|gm| (j, gm))).collect();
+
+    // Newtonian potential at each body
+    let mut potentials = vec![0.0; n];
+    for i in 0..n {
+        for &(k, gm) in &massive {
+            if k != i {
+                potentials[i] += gm / (positions[i] - positions[k]).norm();
+            }
+        }
+    }
+
+    let mut corrections = vec![Vector3::zeros(); n];
+    for i in 0..n {
+        let v_i = velocities[i];
+        let v_i_squared = v_i.dot(&v_i);
+        for &(j, gm) in &massive {
+            if j == i {
+                continue;
+            }
+            let r_ij = positions[i] - positions[j];
+            let r = r_ij.norm();
+            let r3 = r * r * r;
+            let v_j = velocities[j];
+            let a_j = newtonian[j];
+
+            let rv = r_ij.dot(&v_j) / r;
+            let bracket = -4.0 * potentials[i] - potentials[j]
+                + v_i_squared + 2.0 * v_j.dot(&v_j) - 4.0 * v_i.dot(&v_j)
+                - 1.5 * rv * rv - 0.5 * r_ij.dot(&a_j);
+
+            corrections[i] += -gm * r_ij / r3 * bracket / C2;
+            corrections[i] += gm / r3 * r_ij.dot(&(4.0 * v_i - 3.0 * v_j)) * (v_i - v_j) / C2;
+            corrections[i] += 3.5 * gm * a_j / (r * C2);
+        }
+    }
+
+    corrections
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::constants::GM_SUN;
+    use crate::spacerock::SpaceRock;
+    use crate::forces::ForceModel;
+    use crate::integrate::{integrate, RK45};
+
+    const EPOCH: f64 = 2451545.0;
+    const DECADE: f64 = 3652.5;
+
+    // Where mercury's perihelion points after a decade, starting from perihelion on the x axis.
+    fn perihelion_after_a_decade(relativity: Relativity) -> f64 {
+        let (a, e) = (0.387098, 0.205630);
+        let mut sun = SpaceRock::from_xyz("Sun", 0.0, 0.0, 0.0, 0.0, 0.0, 0.0, EPOCH);
+        sun.mass = Some(GM_SUN);
+        let speed = (GM_SUN * (1.0 + e) / (a * (1.0 - e))).sqrt();
+        let mercury = SpaceRock::from_xyz("Mercury", a * (1.0 - e), 0.0, 0.0, 0.0, speed, 0.0, EPOCH);
+        let mut rocks = vec![sun, mercury];
+
+        integrate(&mut rocks, &ForceModel::new().with_relativity(relativity), &RK45::new(1.0, 1e-12), EPOCH + DECADE).unwrap();
+
+        // the Laplace-Runge-Lenz vector points to the perihelion
+        let (r, v) = (rocks[1].position - rocks[0].position, rocks[1].velocity - rocks[0].velocity);
+        let lrl = (v.norm_squared() - GM_SUN / r.norm()) * r - r.dot(&v) * v;
+        lrl.y.atan2(lrl.x)
+    }
+
+    // Measured against the Newtonian orbit, so the integration error cancels.
+    #[test]
+    fn mercury_perihelion_advances_43_arcseconds_a_century() {
+        let newtonian = perihelion_after_a_decade(Relativity::Newtonian);
+        for relativity in [Relativity::Schwarzschild, Relativity::EIH] {
+            let advance = 10.0 * (perihelion_after_a_decade(relativity) - newtonian).to_degrees() * 3600.0;
+            assert!((advance - 42.98).abs() < 0.5, "{:?}: {:.2}\" a century", relativity, advance);
+        }
+    }
+}
@@ -0,0 +1,279 @@
+use crate::spacerock::SpaceRock;
+use crate::keplerorbit::KeplerOrbit;
+use crate::forces::ForceModel;
+use crate::integrate::Integrator;
+use crate::events::Handlers;
+use crate::frame::Frame;
+use crate::error::{Result, SpaceRocksError};
+
+use std::f64::consts::PI;
+
+const TWO_PI: f64 = 2.0 * PI;
+
+// An angle librates if its samples leave a gap at least this wide (radians) somewhere on the circle,
+// so the sampling has to be fine enough that a circulating angle leaves no such gap.
+const LIBRATION_GAP: f64 = 10.0 * PI / 180.0;
+
+// Resonances with more rock orbits than this are too weak to matter.
+const MAX_Q: u32 = 50;
+
+// Osculating elements of a body sampled along an integration.
+#[derive(Clone, Debug)]
+pub struct ElementHistory {
+    pub name: String,
+    pub epochs: Vec<f64>,
+    pub orbits: Vec<KeplerOrbit>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct MeanElements {
+    pub a: f64,
+    pub e: f64,
+    pub inc: f64,
+    pub a_min: f64,
+    pub a_max: f64,
+}
+
+impl ElementHistory {
+
+    // Integrate the rocks through the epochs, recording the elements of the rocks and of the ephemeris
+    // perturbers in the given frame at each one. Elements are barycentric if the rocks are, and the sun is skipped.
+    pub fn record(rocks: &mut Vec<SpaceRock>, forces: &ForceModel, integrator: &Integrator, epochs: &[f64], frame: &Frame) -> Result<Vec<ElementHistory>> {
+
+        let handlers = Handlers::new();
+        let mut histories: Vec<ElementHistory> = Vec::new();
+
+        for &epoch in epochs {
+            integrator.integrate(rocks, forces, &handlers, epoch)?;
+            let perturbers = forces.perturber_states(epoch)?;
+            for rock in rocks.iter().chain(&perturbers) {
+                if rock.name.eq_ignore_ascii_case("sun") {
+                    continue;
+                }
+                let mut rock = rock.clone();
+                rock.change_frame(frame)?;
+                let orbit = rock.kepler_orbit();
+                match histories.iter_mut().find(|history| history.name == rock.name) {
+                    Some(history) => {
+                        history.epochs.push(epoch);
+                        history.orbits.push(orbit);
+                    }
+                    None => histories.push(ElementHistory { name: rock.name, epochs: vec![epoch], orbits: vec![orbit] }),
+                }
+            }
+        }
+
+        Ok(histories)
+    }
+
+    // A history has to have elements for each of its epochs, and at least one of them.
+    pub(crate) fn check(&self) -> Result<()> {
+        if self.orbits.is_empty() {
+            return Err(SpaceRocksError::InvalidInput(format!("{} has an empty element history", self.name)));
+        }
+        if self.orbits.len() != self.epochs.len() {
+            return Err(SpaceRocksError::InvalidInput(format!("{} has {} epochs but {} sets of elements", self.name, self.epochs.len(), self.orbits.len())));
+        }
+        Ok(())
+    }
+
+    // Averages over the whole history, with the range of the semimajor axis.
+    pub fn mean_elements(&self) -> Result<MeanElements> {
+        self.check()?;
+        let n = self.orbits.len() as f64;
+        Ok(MeanElements {
+            a: self.orbits.iter().map(|orbit| orbit.a).sum::<f64>() / n,
+            e: self.orbits.iter().map(|orbit| orbit.e).sum::<f64>() / n,
+            inc: self.orbits.iter().map(|orbit| orbit.inc).sum::<f64>() / n,
+            a_min: self.orbits.iter().map(|orbit| orbit.a).fold(f64::INFINITY, f64::min),
+            a_max: self.orbits.iter().map(|orbit| orbit.a).fold(f64::NEG_INFINITY, f64::max),
+        })
+    }
+
+    // A running mean of a, e and inc over a window in days, to filter out the short-period terms.
+    // The angles are left osculating.
+    pub fn smoothed(&self, window: f64) -> Result<ElementHistory> {
+
+        self.check()?;
+        check_window(window)?;
+
+        let n = self.orbits.len();
+        let mut orbits = self.orbits.clone();
+        let (mut lo, mut hi) = (0, 0);
+        let mut sums = [0.0; 3];
+
+        for (i, orbit) in orbits.iter_mut().enumerate() {
+            while hi < n && self.epochs[hi] - self.epochs[i] <= 0.5 * window {
+                sums[0] += self.orbits[hi].a;
+                sums[1] += self.orbits[hi].e;
+                sums[2] += self.orbits[hi].inc;
+                hi += 1;
+            }
+            while self.epochs[i] - self.epochs[lo] > 0.5 * window {
+                sums[0] -= self.orbits[lo].a;
+                sums[1] -= self.orbits[lo].e;
+                sums[2] -= self.orbits[lo].inc;
+                lo += 1;
+            }
+            let count = (hi - lo) as f64;
+            orbit.a = sums[0] / count;
+            orbit.e = sums[1] / count;
+            orbit.inc = sums[2] / count;
+        }
+
+        Ok(ElementHistory { name: self.name.clone(), epochs: self.epochs.clone(), orbits })
+    }
+}
+
+// The d'Alembert argument p λ - q λ' - eccentricity ϖ - inclination Ω of a p:q mean-motion resonance
+// with a perturber (primed), where the rock completes q orbits while the perturber completes p, so the
+// plutinos are in the 3:2. The coefficients sum to zero and the inclination one is even.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ResonantAngle {
+    pub p: u32,
+    pub q: u32,
+    pub eccentricity: i32,
+    pub inclination: i32,
+}
+
+impl ResonantAngle {
+
+    // Every argument of the resonance that depends only on the rock's ϖ and Ω, leading with the pure eccentricity one.
+    pub fn candidates(p: u32, q: u32) -> Vec<ResonantAngle> {
+        let order = p as i32 - q as i32;
+        (0..=order.abs()).step_by(2)
+            .map(|k| {
+                let inclination = k * order.signum();
+                ResonantAngle { p, q, eccentricity: order - inclination, inclination }
+            })
+            .collect()
+    }
+
+    pub fn order(&self) -> u32 {
+        self.p.abs_diff(self.q)
+    }
+
+    // The angle in [0, 2π).
+    pub fn angle(&self, rock: &KeplerOrbit, perturber: &KeplerOrbit) -> f64 {
+        let varpi = rock.node + rock.arg;
+        let phi = self.p as f64 * mean_longitude(rock) - self.q as f64 * mean_longitude(perturber)
+                - self.eccentricity as f64 * varpi - self.inclination as f64 * rock.node;
+        phi.rem_euclid(TWO_PI)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Libration {
+    pub center: f64,    // middle of the range the angle covers, in [0, 2π)
+    pub amplitude: f64, // half the peak-to-peak range
+}
+
+impl Libration {
+
+    // The arc of the circle covered by the angles, or None if they circulate.
+    pub fn from_angles(angles: &[f64]) -> Option<Self> {
+
+        if angles.is_empty() {
+            return None;
+        }
+
+        let mut sorted: Vec<f64> = angles.iter().map(|angle| angle.rem_euclid(TWO_PI)).collect();
+        sorted.sort_by(|a, b| a.total_cmp(b));
+
+        // the largest empty arc, starting with the one that wraps through zero
+        let mut gap = sorted[0] + TWO_PI - sorted[sorted.len() - 1];
+        let mut start = sorted[0];
+        for pair in sorted.windows(2) {
+            if pair[1] - pair[0] > gap {
+                gap = pair[1] - pair[0];
+                start = pair[1];
+            }
+        }
+
+        if gap < LIBRATION_GAP {
+            return None;
+        }
+
+        let amplitude = 0.5 * (TWO_PI - gap);
+        Some(Libration { center: (start + amplitude).rem_euclid(TWO_PI), amplitude })
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Resonance {
+    pub angle: ResonantAngle,
+    pub libration: Option<Libration>, // over the whole history
+    pub fraction: f64,                // of the windows in which the angle librates
+}
+
+impl Resonance {
+
+    // Resonant in the Gladman et al. (2008) sense: librating for the whole integration.
+    pub fn is_resonant(&self) -> bool {
+        self.libration.is_some()
+    }
+}
+
+// Look for mean-motion resonances of a rock with a perturber (normally neptune) from histories sampled at the
+// same epochs, checking every p:q up to the given order whose nominal location lies within the range the rock's
+// semimajor axis covers. The history is split into windows to show intermittent libration, and resonances
+// whose angles never librate are left out. Sorted with the longest-librating first.
+pub fn find_resonances(rock: &ElementHistory, perturber: &ElementHistory, max_order: u32, windows: usize) -> Result<Vec<Resonance>> {
+
+    let mean = rock.mean_elements()?;
+    let a_perturber = perturber.mean_elements()?.a;
+    if rock.epochs != perturber.epochs {
+        return Err(SpaceRocksError::InvalidInput(format!("{} and {} weren't sampled at the same epochs", rock.name, perturber.name)));
+    }
+    let windows = windows.max(1);
+    let chunk = rock.orbits.len().div_ceil(windows).max(1);
+
+    let mut resonances = Vec::new();
+    for q in 1..=MAX_Q {
+        let lo = q.saturating_sub(max_order).max(1);
+        for p in lo..=q + max_order {
+            if gcd(p, q) != 1 {
+                continue;
+            }
+            let a_resonance = a_perturber * (p as f64 / q as f64).powf(2.0 / 3.0);
+            if a_resonance < mean.a_min || a_resonance > mean.a_max {
+                continue;
+            }
+            for angle in ResonantAngle::candidates(p, q) {
+                let angles: Vec<f64> = rock.orbits.iter().zip(&perturber.orbits)
+                    .map(|(orbit, other)| angle.angle(orbit, other))
+                    .collect();
+                let librating = angles.chunks(chunk).filter(|window| Libration::from_angles(window).is_some()).count();
+                if librating == 0 {
+                    continue;
+                }
+                resonances.push(Resonance {
+                    angle,
+                    libration: Libration::from_angles(&angles),
+                    fraction: librating as f64 / angles.chunks(chunk).count() as f64,
+                });
+            }
+        }
+    }
+
+    resonances.sort_by(|a, b| b.fraction.total_cmp(&a.fraction).then(a.angle.order().cmp(&b.angle.order())));
+    Ok(resonances)
+}
+
+pub(crate) fn check_window(window: f64) -> Result<()> {
+    if !window.is_finite() || window < 0.0 {
+        return Err(SpaceRocksError::InvalidInput(format!("smoothing window must be finite and non-negative, not {}", window)));
+    }
+    Ok(())
+}
+
+// λ = Ω + ω + M, for a bound orbit.
+pub fn mean_longitude(orbit: &KeplerOrbit) -> f64 {
+    let big_e = 2.0 * ((1.0 - orbit.e).sqrt() * (0.5 * orbit.f).sin()).atan2((1.0 + orbit.e).sqrt() * (0.5 * orbit.f).cos());
+    let mean_anomaly = big_e - orbit.e * big_e.sin();
+    (orbit.node + orbit.arg + mean_anomaly).rem_euclid(TWO_PI)
+}
+
+fn gcd(a: u32, b: u32) -> u32 {
+    if b == 0 { a } else { gcd(b, a % b) }
+}
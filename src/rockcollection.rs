@@ -0,0 +1,217 @@
+use crate::spacerock::SpaceRock;
+use crate::error::{Result, SpaceRocksError};
+
+use arrow::array::{Array, ArrayAccessor, ArrayRef, AsArray, DictionaryArray, Float64Array, StringArray};
+use arrow::compute::{cast, concat_batches};
+use arrow::datatypes::{DataType, Field, Float64Type, Int32Type, Schema, SchemaRef};
+use arrow::record_batch::{RecordBatch, RecordBatchReader};
+use parquet::arrow::ArrowWriter;
+use parquet::arrow::arrow_reader::ParquetRecordBatchReaderBuilder;
+use parquet::file::reader::ChunkReader;
+
+use std::io::Write;
+use std::sync::Arc;
+
+// A catalog of rocks held column by column, for populations too large for a Vec<SpaceRock>. Frames and
+// origins are dictionary encoded, so a million rocks in J2000 about the SSB store each string once; mass,
+// radius, h and g are null where unknown. Nongravs and covariances aren't carried.
+#[derive(Clone, Debug)]
+pub struct RockCollection {
+    pub name: StringArray,
+    pub epoch: Float64Array,
+    pub frame: DictionaryArray<Int32Type>,
+    pub origin: DictionaryArray<Int32Type>,
+    pub x: Float64Array,
+    pub y: Float64Array,
+    pub z: Float64Array,
+    pub vx: Float64Array,
+    pub vy: Float64Array,
+    pub vz: Float64Array,
+    pub mass: Float64Array,
+    pub radius: Float64Array,
+    pub h: Float64Array,
+    pub g: Float64Array,
+}
+
+const STATE: [&str; 7] = ["epoch", "x", "y", "z", "vx", "vy", "vz"];
+const OPTIONAL: [&str; 4] = ["mass", "radius", "h", "g"];
+
+impl RockCollection {
+
+    pub fn from_rocks(rocks: &[SpaceRock]) -> Self {
+        let column = |value: fn(&SpaceRock) -> f64| Float64Array::from_iter_values(rocks.iter().map(value));
+        let optional = |value: fn(&SpaceRock) -> Option<f64>| rocks.iter().map(value).collect::<Float64Array>();
+        let frames: Vec<String> = rocks.iter().map(|rock| rock.frame.name()).collect();
+        RockCollection {
+            name: StringArray::from_iter_values(rocks.iter().map(|rock| rock.name.as_str())),
+            epoch: column(|rock| rock.epoch),
+            frame: frames.iter().map(String::as_str).collect(),
+            origin: rocks.iter().map(|rock| rock.origin.as_str()).collect(),
+            x: column(|rock| rock.position.x),
+            y: column(|rock| rock.position.y),
+            z: column(|rock| rock.position.z),
+            vx: column(|rock| rock.velocity.x),
+            vy: column(|rock| rock.velocity.y),
+            vz: column(|rock| rock.velocity.z),
+            mass: optional(|rock| rock.mass),
+            radius: optional(|rock| rock.radius),
+            h: optional(|rock| rock.h),
+            g: optional(|rock| rock.g),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.name.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.name.is_empty()
+    }
+
+    pub fn rock(&self, index: usize) -> Result<SpaceRock> {
+        if index >= self.len() {
+            return Err(SpaceRocksError::InvalidInput(format!("no rock {} in a collection of {}", index, self.len())));
+        }
+        let label = |array: &DictionaryArray<Int32Type>| -> Result<String> {
+            let values = array.downcast_dict::<StringArray>()
+                .ok_or_else(|| SpaceRocksError::Parse("frame and origin must be dictionaries of strings".to_string()))?;
+            Ok(values.value(index).to_string())
+        };
+        let optional = |array: &Float64Array| array.is_valid(index).then(|| array.value(index));
+
+        let mut rock = SpaceRock::from_xyz(self.name.value(index),
+                                           self.x.value(index), self.y.value(index), self.z.value(index),
+                                           self.vx.value(index), self.vy.value(index), self.vz.value(index),
+                                           self.epoch.value(index));
+        rock.frame = label(&self.frame)?.parse()?;
+        rock.origin = label(&self.origin)?;
+        rock.mass = optional(&self.mass);
+        rock.radius = optional(&self.radius);
+        rock.h = optional(&self.h);
+        rock.g = optional(&self.g);
+        Ok(rock)
+    }
+
+    pub fn to_rocks(&self) -> Result<Vec<SpaceRock>> {
+        (0..self.len()).map(|index| self.rock(index)).collect()
+    }
+
+    // Distances from the origin, straight off the columns.
+    pub fn r(&self) -> Float64Array {
+        let (x, y, z) = (self.x.values(), self.y.values(), self.z.values());
+        Float64Array::from_iter_values((0..self.len()).map(|i| (x[i] * x[i] + y[i] * y[i] + z[i] * z[i]).sqrt()))
+    }
+
+    pub fn schema() -> SchemaRef {
+        let label = DataType::Dictionary(Box::new(DataType::Int32), Box::new(DataType::Utf8));
+        let mut fields = vec![Field::new("name", DataType::Utf8, false),
+                              Field::new("frame", label.clone(), false),
+                              Field::new("origin", label, false)];
+        fields.extend(STATE.iter().map(|name| Field::new(*name, DataType::Float64, false)));
+        fields.extend(OPTIONAL.iter().map(|name| Field::new(*name, DataType::Float64, true)));
+        Arc::new(Schema::new(fields))
+    }
+
+    pub fn to_record_batch(&self) -> Result<RecordBatch> {
+        let columns: Vec<ArrayRef> = vec![Arc::new(self.name.clone()), Arc::new(self.frame.clone()), Arc::new(self.origin.clone()),
+                                          Arc::new(self.epoch.clone()),
+                                          Arc::new(self.x.clone()), Arc::new(self.y.clone()), Arc::new(self.z.clone()),
+                                          Arc::new(self.vx.clone()), Arc::new(self.vy.clone()), Arc::new(self.vz.clone()),
+                                          Arc::new(self.mass.clone()), Arc::new(self.radius.clone()),
+                                          Arc::new(self.h.clone()), Arc::new(self.g.clone())];
+        Ok(RecordBatch::try_new(Self::schema(), columns)?)
+    }
+
+    // Take the columns by name, casting where another tool stored them differently (e.g. polars' large or
+    // view strings, or frames as plain strings). The optional columns may be left out.
+    pub fn from_record_batch(batch: &RecordBatch) -> Result<Self> {
+        let schema = Self::schema();
+        let column = |name: &str| -> Result<ArrayRef> {
+            let field = schema.field_with_name(name)?;
+            match batch.column_by_name(name) {
+                Some(column) if column.null_count() > 0 && !field.is_nullable() => {
+                    Err(SpaceRocksError::Parse(format!("column {} has nulls", name)))
+                }
+                Some(column) => Ok(cast(column, field.data_type())?),
+                None if field.is_nullable() => Ok(Arc::new(Float64Array::new_null(batch.num_rows()))),
+                None => Err(SpaceRocksError::Parse(format!("missing column {}", name))),
+            }
+        };
+        let float = |name: &str| -> Result<Float64Array> { Ok(column(name)?.as_primitive::<Float64Type>().clone()) };
+        let label = |name: &str| -> Result<DictionaryArray<Int32Type>> { Ok(column(name)?.as_dictionary::<Int32Type>().clone()) };
+
+        Ok(RockCollection {
+            name: column("name")?.as_string::<i32>().clone(),
+            epoch: float("epoch")?,
+            frame: label("frame")?,
+            origin: label("origin")?,
+            x: float("x")?,
+            y: float("y")?,
+            z: float("z")?,
+            vx: float("vx")?,
+            vy: float("vy")?,
+            vz: float("vz")?,
+            mass: float("mass")?,
+            radius: float("radius")?,
+            h: float("h")?,
+            g: float("g")?,
+        })
+    }
+
+    pub fn write_parquet<W: Write + Send>(&self, writer: W) -> Result<()> {
+        let mut writer = ArrowWriter::try_new(writer, Self::schema(), None)?;
+        writer.write(&self.to_record_batch()?)?;
+        writer.close()?;
+        Ok(())
+    }
+
+    // Read a whole parquet file, e.g. a std::fs::File, into one collection.
+    pub fn read_parquet<R: ChunkReader + 'static>(reader: R) -> Result<Self> {
+        let reader = ParquetRecordBatchReaderBuilder::try_new(reader)?.build()?;
+        let schema = reader.schema();
+        let batches = reader.collect::<std::result::Result<Vec<_>, _>>()?;
+        Self::from_record_batch(&concat_batches(&schema, &batches)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::frame::Frame;
+
+    use std::fs::File;
+
+    fn rocks() -> Vec<SpaceRock> {
+        let mut first = SpaceRock::from_xyz("2004 MN4", 0.92, -0.31, 0.04, 0.006, 0.015, -0.0004, 2460000.5);
+        first.h = Some(19.09);
+        first.g = Some(0.24);
+        first.radius = Some(1.2e-9);
+        let mut second = SpaceRock::from_xyz("Jupiter Barycenter", -3.4, 4.1, 0.06, -0.0059, -0.0045, 0.0002, 2460010.5);
+        second.frame = Frame::EclipJ2000;
+        second.origin = "Sun".to_string();
+        second.mass = Some(2.825e-7);
+        vec![first, second]
+    }
+
+    #[test]
+    fn parquet_round_trips() {
+        let path = std::env::temp_dir().join(format!("spacerocks-collection-{}.parquet", std::process::id()));
+        RockCollection::from_rocks(&rocks()).write_parquet(File::create(&path).unwrap()).unwrap();
+        let read = RockCollection::read_parquet(File::open(&path).unwrap()).unwrap().to_rocks().unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(read.len(), 2);
+        for (a, b) in read.iter().zip(&rocks()) {
+            assert_eq!((&a.name, a.epoch, &a.frame, &a.origin), (&b.name, b.epoch, &b.frame, &b.origin));
+            assert_eq!((a.position, a.velocity), (b.position, b.velocity));
+            assert_eq!((a.mass, a.radius, a.h, a.g), (b.mass, b.radius, b.h, b.g));
+        }
+    }
+
+    #[test]
+    fn a_rock_past_the_end_is_an_error() {
+        let collection = RockCollection::from_rocks(&rocks());
+        assert!(collection.rock(1).is_ok());
+        assert!(matches!(collection.rock(2), Err(SpaceRocksError::InvalidInput(_))));
+    }
+}
@@ -0,0 +1,109 @@
+use crate::spacerock::SpaceRock;
+use crate::keplerorbit::KeplerOrbit;
+use crate::calc_E_from_M::calc_f_from_M;
+use crate::constants::{DEG_TO_RAD, GM_SUN};
+use crate::mpcorb::CatalogOrbit;
+use crate::error::{Result, SpaceRocksError};
+
+use std::io::Read;
+
+// Read a csv exported from JPL's small-body database query. The columns are found by their SBDB field
+// names, so any selection and order will do as long as it has
+//   full_name, pdes or name;  epoch (JD TDB) or epoch_mjd;  e, i, om and w (degrees);
+//   a or q (au);  ma (degrees) or tp (JD TDB)
+// and optionally H and G. Orbits given by q and tp, as for most comets, may be hyperbolic but not parabolic.
+// Each becomes a barycentric rock in ecliptic J2000. Needs the leapseconds and an ephemeris loaded.
+pub fn read_sbdb<R: Read>(reader: R) -> Result<Vec<SpaceRock>> {
+    read_orbits(reader)?.iter().map(CatalogOrbit::to_rock).collect()
+}
+
+fn read_orbits<R: Read>(reader: R) -> Result<Vec<CatalogOrbit>> {
+    let mut reader = csv::ReaderBuilder::new().trim(csv::Trim::All).from_reader(reader);
+    let headers = reader.headers()?.clone();
+    let index = |names: &[&str]| names.iter().find_map(|name| headers.iter().position(|header| header == *name));
+    let missing = |names: &[&str]| SpaceRocksError::Parse(format!("the SBDB csv needs a column {}", names.join(" or ")));
+    let require = |names: &[&str]| index(names).ok_or_else(|| missing(names));
+
+    let name = require(&["full_name", "pdes", "name"])?;
+    let (epoch, mjd) = match (index(&["epoch"]), index(&["epoch_mjd"])) {
+        (Some(epoch), _) => (epoch, false),
+        (None, Some(epoch)) => (epoch, true),
+        _ => return Err(missing(&["epoch", "epoch_mjd"])),
+    };
+    let (e, i, om, w) = (require(&["e"])?, require(&["i"])?, require(&["om"])?, require(&["w"])?);
+    let (a, q) = (index(&["a"]), index(&["q"]));
+    let (ma, tp) = (index(&["ma"]), index(&["tp"]));
+    if a.is_none() && q.is_none() {
+        return Err(missing(&["a", "q"]));
+    }
+    if ma.is_none() && tp.is_none() {
+        return Err(missing(&["ma", "tp"]));
+    }
+    let (h, g) = (index(&["H"]), index(&["G"]));
+
+    let mut orbits = Vec::new();
+    for record in reader.records() {
+        let record = record?;
+        let line = record.position().map_or(0, |position| position.line());
+        let value = |column: Option<usize>| column.and_then(|column| record.get(column)).and_then(|field| field.parse::<f64>().ok());
+        let number = |column: usize, what: &str| {
+            value(Some(column)).ok_or_else(|| SpaceRocksError::Parse(format!("line {} of the SBDB csv has no {}", line, what)))
+        };
+
+        let name = record.get(name).unwrap_or("");
+        let epoch = number(epoch, "epoch")? + if mjd { 2400000.5 } else { 0.0 };
+        let e = number(e, "e")?;
+        let a = match (value(a), value(q)) {
+            (Some(a), _) => a,
+            (None, Some(q)) if e != 1.0 => q / (1.0 - e),
+            _ => return Err(SpaceRocksError::InvalidInput(format!("{} has no semimajor axis", name))),
+        };
+        let mean_anomaly = match (value(ma), value(tp)) {
+            (Some(ma), _) => ma * DEG_TO_RAD,
+            (None, Some(tp)) => (GM_SUN / a.abs().powi(3)).sqrt() * (epoch - tp),
+            _ => return Err(SpaceRocksError::Parse(format!("line {} of the SBDB csv has neither ma nor tp", line))),
+        };
+
+        orbits.push(CatalogOrbit {
+            name: name.to_string(),
+            epoch,
+            orbit: KeplerOrbit::new(a, e, number(i, "i")? * DEG_TO_RAD, number(w, "w")? * DEG_TO_RAD,
+                                    number(om, "om")? * DEG_TO_RAD, calc_f_from_M(e, mean_anomaly)),
+            h: value(h),
+            g: value(g),
+        });
+    }
+    Ok(orbits)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn a_hyperbolic_orbit_is_read_from_q_and_tp() {
+        let csv = "full_name,epoch,e,q,tp,i,om,w,H\n\
+                   1I/'Oumuamua (A/2017 U1),2458080.5,1.201134,.2553469,2458006.007321,122.74,24.597,241.81,22.1\n";
+        let orbits = read_orbits(csv.as_bytes()).unwrap();
+        assert_eq!(orbits.len(), 1);
+
+        let orbit = &orbits[0];
+        assert_eq!(orbit.name, "1I/'Oumuamua (A/2017 U1)");
+        assert_eq!((orbit.h, orbit.g), (Some(22.1), None));
+        let (a, e, f) = (orbit.orbit.a, orbit.orbit.e, orbit.orbit.f);
+        assert!(a < 0.0);
+        assert!((a * (1.0 - e) - 0.2553469).abs() < 1e-12);
+
+        // back from the true anomaly to the hyperbolic mean anomaly, which grows from tp at the mean motion
+        let anomaly = 2.0 * (((e - 1.0) / (e + 1.0)).sqrt() * (f / 2.0).tan()).atanh();
+        let mean_anomaly = e * anomaly.sinh() - anomaly;
+        let expected = (GM_SUN / (-a).powi(3)).sqrt() * (2458080.5 - 2458006.007321);
+        assert!((mean_anomaly - expected).abs() < 1e-10);
+    }
+
+    #[test]
+    fn a_missing_column_is_named() {
+        let csv = "full_name,epoch,e,a,i,om,w\n433 Eros (A898 PA),2460600.5,.2228,1.4581,10.83,304.28,178.93\n";
+        assert!(matches!(read_orbits(csv.as_bytes()), Err(SpaceRocksError::Parse(message)) if message.contains("ma or tp")));
+    }
+}
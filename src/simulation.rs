@@ -0,0 +1,514 @@
+use crate::spacerock::SpaceRock;
+use crate::frame::Frame;
+use crate::error::{Result, SpaceRocksError};
+use crate::forces::ForceModel;
+use crate::integrate::{Coordinates, Integrator, WHFast, RK45};
+use crate::mercurius::Mercurius;
+use crate::relativity::Relativity;
+use crate::nongravs::NonGravs;
+use crate::covariance::Covariance;
+use crate::events::{CollisionHandler, EjectionHandler, Event, Handlers};
+
+use nalgebra::{Matrix3, Matrix6, Vector3};
+
+use std::fs::File;
+use std::io::{self, BufWriter, Read, Write};
+use std::path::Path;
+
+// identifies a checkpoint file and the version of its layout
+const MAGIC: &[u8; 8] = b"SPRKSIM1";
+
+// A set of rocks integrated together under one force model, with the events of the run so far.
+pub struct Simulation {
+    pub rocks: Vec<SpaceRock>,
+    pub forces: ForceModel,
+    pub integrator: Integrator,
+    pub handlers: Handlers,
+    pub epoch: f64,
+    pub events: Vec<Event>,
+}
+
+impl Simulation {
+
+    // The rocks must share an epoch, which becomes the current time of the simulation.
+    pub fn new(rocks: Vec<SpaceRock>, forces: ForceModel, integrator: Integrator) -> Result<Self> {
+        let epoch = rocks.first().map_or(0.0, |rock| rock.epoch);
+        if rocks.iter().any(|rock| rock.epoch != epoch) {
+            return Err(SpaceRocksError::InvalidInput("the rocks must share an epoch".to_string()));
+        }
+        Ok(Simulation {
+            rocks,
+            forces,
+            integrator,
+            handlers: Handlers::default(),
+            epoch,
+            events: Vec::new(),
+        })
+    }
+
+    pub fn with_handlers(mut self, handlers: Handlers) -> Self {
+        self.handlers = handlers;
+        self
+    }
+
+    // Returns false if a handler halted the integration, in which case the simulation is left
+    // at the epoch of the last event.
+    pub fn integrate(&mut self, epoch: f64) -> Result<bool> {
+        if !self.rocks.is_empty() {
+            let events = self.integrator.integrate(&mut self.rocks, &self.forces, &self.handlers, epoch)?;
+            let halt = events.last().filter(|event| self.handlers.halts(event)).map(|event| event.epoch());
+            self.events.extend(events);
+            if let Some(halt) = halt {
+                self.epoch = halt;
+                return Ok(false);
+            }
+        }
+        self.epoch = epoch;
+        Ok(true)
+    }
+
+    // Integrate through each of the output epochs in turn, writing the state of every rock at each one as csv.
+    // Stops after the snapshot at a halt.
+    pub fn integrate_with_snapshots<P: AsRef<Path>>(&mut self, epochs: &[f64], path: P) -> Result<()> {
+        let mut file = BufWriter::new(File::create(path)?);
+        file.write_all(b"name,epoch,x,y,z,vx,vy,vz\n")?;
+        for &epoch in epochs {
+            let finished = self.integrate(epoch)?;
+            self.write_snapshot(&mut file)?;
+            file.flush()?;
+            if !finished {
+                break;
+            }
+        }
+        Ok(())
+    }
+
+    pub fn write_snapshot<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        for rock in &self.rocks {
+            writeln!(writer, "{},{},{},{},{},{},{},{}", rock.name, rock.epoch,
+                     rock.position.x, rock.position.y, rock.position.z,
+                     rock.velocity.x, rock.velocity.y, rock.velocity.z)?;
+        }
+        Ok(())
+    }
+
+    // Write a binary checkpoint from which the simulation can be restored exactly.
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+
+        let mut bytes = MAGIC.to_vec();
+        put_f64(&mut bytes, self.epoch);
+
+        put_u64(&mut bytes, self.rocks.len() as u64);
+        for rock in &self.rocks {
+            put_rock(&mut bytes, rock);
+        }
+
+        bytes.push(self.forces.nongravs as u8);
+        bytes.push(match self.forces.relativity {
+            Relativity::Newtonian => 0,
+            Relativity::Schwarzschild => 1,
+            Relativity::EIH => 2,
+        });
+        put_u64(&mut bytes, self.forces.perturbers.len() as u64);
+        for name in &self.forces.perturbers {
+            put_str(&mut bytes, name);
+        }
+
+        match &self.integrator {
+            Integrator::RK45(integrator) => {
+                bytes.push(0);
+                put_rk45(&mut bytes, integrator);
+            }
+            Integrator::WHFast(integrator) => {
+                bytes.push(1);
+                put_f64(&mut bytes, integrator.timestep);
+                bytes.push(match integrator.coordinates {
+                    Coordinates::DemocraticHeliocentric => 0,
+                    Coordinates::Jacobi => 1,
+                });
+                bytes.push(integrator.corrector);
+            }
+            Integrator::Mercurius(integrator) => {
+                bytes.push(2);
+                put_f64(&mut bytes, integrator.timestep);
+                put_f64(&mut bytes, integrator.changeover);
+                put_rk45(&mut bytes, &integrator.encounter);
+            }
+        }
+
+        bytes.push(match self.handlers.collision {
+            CollisionHandler::Halt => 0,
+            CollisionHandler::Merge => 1,
+            CollisionHandler::Bounce => 2,
+            CollisionHandler::Remove => 3,
+        });
+        bytes.push(match self.handlers.ejection {
+            EjectionHandler::Halt => 0,
+            EjectionHandler::Remove => 1,
+        });
+        put_f64(&mut bytes, self.handlers.ejection_distance);
+
+        put_u64(&mut bytes, self.events.len() as u64);
+        for event in &self.events {
+            match event {
+                Event::Collision { epoch, rocks } => {
+                    bytes.push(0);
+                    put_f64(&mut bytes, *epoch);
+                    put_str(&mut bytes, &rocks.0);
+                    put_str(&mut bytes, &rocks.1);
+                }
+                Event::Ejection { epoch, rock, distance } => {
+                    bytes.push(1);
+                    put_f64(&mut bytes, *epoch);
+                    put_str(&mut bytes, rock);
+                    put_f64(&mut bytes, *distance);
+                }
+            }
+        }
+
+        File::create(path)?.write_all(&bytes)
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+
+        let mut bytes = Vec::new();
+        File::open(path)?.read_to_end(&mut bytes)?;
+
+        let mut reader = Reader { bytes: &bytes, offset: 0 };
+        if reader.take(MAGIC.len())? != MAGIC {
+            return Err(invalid("not a simulation checkpoint"));
+        }
+        let epoch = reader.f64()?;
+
+        let n = reader.u64()?;
+        let mut rocks = Vec::new();
+        for _ in 0..n {
+            rocks.push(reader.rock()?);
+        }
+
+        let nongravs = reader.u8()? != 0;
+        let relativity = match reader.u8()? {
+            0 => Relativity::Newtonian,
+            1 => Relativity::Schwarzschild,
+            2 => Relativity::EIH,
+            _ => return Err(invalid("unknown relativity model")),
+        };
+        let n = reader.u64()?;
+        let mut perturbers = Vec::new();
+        for _ in 0..n {
+            perturbers.push(reader.string()?);
+        }
+        let forces = ForceModel { nongravs, relativity, perturbers };
+
+        let integrator = match reader.u8()? {
+            0 => Integrator::RK45(reader.rk45()?),
+            1 => {
+                let timestep = reader.f64()?;
+                let coordinates = match reader.u8()? {
+                    0 => Coordinates::DemocraticHeliocentric,
+                    1 => Coordinates::Jacobi,
+                    _ => return Err(invalid("unknown coordinates")),
+                };
+                Integrator::WHFast(WHFast::new(timestep, coordinates).with_corrector(reader.u8()?))
+            }
+            2 => Integrator::Mercurius(Mercurius {
+                timestep: reader.f64()?,
+                changeover: reader.f64()?,
+                encounter: reader.rk45()?,
+            }),
+            _ => return Err(invalid("unknown integrator")),
+        };
+
+        let collision = match reader.u8()? {
+            0 => CollisionHandler::Halt,
+            1 => CollisionHandler::Merge,
+            2 => CollisionHandler::Bounce,
+            3 => CollisionHandler::Remove,
+            _ => return Err(invalid("unknown collision handler")),
+        };
+        let ejection = match reader.u8()? {
+            0 => EjectionHandler::Halt,
+            1 => EjectionHandler::Remove,
+            _ => return Err(invalid("unknown ejection handler")),
+        };
+        let handlers = Handlers { collision, ejection, ejection_distance: reader.f64()? };
+
+        let n = reader.u64()?;
+        let mut events = Vec::new();
+        for _ in 0..n {
+            events.push(match reader.u8()? {
+                0 => Event::Collision { epoch: reader.f64()?, rocks: (reader.string()?, reader.string()?) },
+                1 => Event::Ejection { epoch: reader.f64()?, rock: reader.string()?, distance: reader.f64()? },
+                _ => return Err(invalid("unknown event")),
+            });
+        }
+
+        Ok(Simulation {
+            rocks,
+            forces,
+            integrator,
+            handlers,
+            epoch,
+            events,
+        })
+    }
+}
+
+// Checkpoints are little-endian, with strings and lists prefixed by their length and optional values by a flag byte.
+
+fn put_u64(bytes: &mut Vec<u8>, value: u64) {
+    bytes.extend_from_slice(&value.to_le_bytes());
+}
+
+fn put_f64(bytes: &mut Vec<u8>, value: f64) {
+    bytes.extend_from_slice(&value.to_le_bytes());
+}
+
+fn put_str(bytes: &mut Vec<u8>, value: &str) {
+    put_u64(bytes, value.len() as u64);
+    bytes.extend_from_slice(value.as_bytes());
+}
+
+fn put_option(bytes: &mut Vec<u8>, value: Option<f64>) {
+    match value {
+        Some(value) => {
+            bytes.push(1);
+            put_f64(bytes, value);
+        }
+        None => bytes.push(0),
+    }
+}
+
+fn put_rk45(bytes: &mut Vec<u8>, integrator: &RK45) {
+    put_f64(bytes, integrator.timestep);
+    put_f64(bytes, integrator.epsilon);
+}
+
+fn put_rock(bytes: &mut Vec<u8>, rock: &SpaceRock) {
+    put_str(bytes, &rock.name);
+    for value in rock.position.iter().chain(rock.velocity.iter()) {
+        put_f64(bytes, *value);
+    }
+    put_f64(bytes, rock.epoch);
+    put_str(bytes, &rock.frame.name());
+    if let Frame::Custom { rotation, .. } = &rock.frame {
+        for value in rotation.iter() {
+            put_f64(bytes, *value);
+        }
+    }
+    put_str(bytes, &rock.origin);
+    put_option(bytes, rock.mass);
+    put_option(bytes, rock.radius);
+    put_option(bytes, rock.h);
+    put_option(bytes, rock.g);
+
+    match &rock.nongravs {
+        Some(nongravs) => {
+            bytes.push(1);
+            put_f64(bytes, nongravs.a2);
+            put_f64(bytes, nongravs.area_to_mass);
+        }
+        None => bytes.push(0),
+    }
+
+    let matrix = match &rock.covariance {
+        Some(Covariance::Cartesian(matrix)) => {
+            bytes.push(1);
+            matrix
+        }
+        Some(Covariance::Keplerian(matrix)) => {
+            bytes.push(2);
+            matrix
+        }
+        None => {
+            bytes.push(0);
+            return;
+        }
+    };
+    for value in matrix.iter() {
+        put_f64(bytes, *value);
+    }
+}
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+    offset: usize,
+}
+
+impl<'a> Reader<'a> {
+
+    fn take(&mut self, n: usize) -> io::Result<&'a [u8]> {
+        // compared this way round so that a corrupt length can't overflow
+        if n > self.bytes.len() - self.offset {
+            return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "checkpoint is truncated"));
+        }
+        let slice = &self.bytes[self.offset..self.offset + n];
+        self.offset += n;
+        Ok(slice)
+    }
+
+    fn u8(&mut self) -> io::Result<u8> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u64(&mut self) -> io::Result<u64> {
+        Ok(u64::from_le_bytes(self.take(8)?.try_into().unwrap()))
+    }
+
+    fn f64(&mut self) -> io::Result<f64> {
+        Ok(f64::from_le_bytes(self.take(8)?.try_into().unwrap()))
+    }
+
+    fn string(&mut self) -> io::Result<String> {
+        let n = self.u64()? as usize;
+        String::from_utf8(self.take(n)?.to_vec()).map_err(|_| invalid("string is not utf-8"))
+    }
+
+    fn option(&mut self) -> io::Result<Option<f64>> {
+        match self.u8()? {
+            0 => Ok(None),
+            _ => Ok(Some(self.f64()?)),
+        }
+    }
+
+    fn vector(&mut self) -> io::Result<Vector3<f64>> {
+        Ok(Vector3::new(self.f64()?, self.f64()?, self.f64()?))
+    }
+
+    fn rk45(&mut self) -> io::Result<RK45> {
+        Ok(RK45::new(self.f64()?, self.f64()?))
+    }
+
+    // A frame by name, followed by its rotation if it isn't a built-in one.
+    fn frame(&mut self) -> io::Result<Frame> {
+        let name = self.string()?;
+        match name.parse() {
+            Ok(Frame::Custom { .. }) | Err(_) => {
+                let mut values = [0.0; 9];
+                for value in values.iter_mut() {
+                    *value = self.f64()?;
+                }
+                Ok(Frame::Custom { name, rotation: Matrix3::from_column_slice(&values) })
+            }
+            Ok(frame) => Ok(frame),
+        }
+    }
+
+    fn rock(&mut self) -> io::Result<SpaceRock> {
+
+        let name = self.string()?;
+        let position = self.vector()?;
+        let velocity = self.vector()?;
+        let epoch = self.f64()?;
+        let frame = self.frame()?;
+        let origin = self.string()?;
+        let mass = self.option()?;
+        let radius = self.option()?;
+        let h = self.option()?;
+        let g = self.option()?;
+
+        let nongravs = match self.u8()? {
+            0 => None,
+            _ => Some(NonGravs::new(self.f64()?, self.f64()?)),
+        };
+
+        let tag = self.u8()?;
+        let covariance = if tag == 0 {
+            None
+        } else {
+            let mut values = [0.0; 36];
+            for value in values.iter_mut() {
+                *value = self.f64()?;
+            }
+            let matrix = Matrix6::from_column_slice(&values);
+            match tag {
+                1 => Some(Covariance::Cartesian(matrix)),
+                2 => Some(Covariance::Keplerian(matrix)),
+                _ => return Err(invalid("unknown covariance representation")),
+            }
+        };
+
+        Ok(SpaceRock {
+            name,
+            position,
+            velocity,
+            epoch,
+            frame,
+            origin,
+            mass,
+            nongravs,
+            covariance,
+            radius,
+            h,
+            g,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::constants::GM_SUN;
+
+    const EPOCH: f64 = 2460000.5;
+
+    fn checkpoint(name: &str) -> std::path::PathBuf {
+        std::env::temp_dir().join(format!("spacerocks-{}-{}.bin", name, std::process::id()))
+    }
+
+    fn simulation() -> Simulation {
+        let mut sun = SpaceRock::from_xyz("Sun", 0.0, 0.0, 0.0, 0.0, 0.0, 0.0, EPOCH);
+        sun.mass = Some(GM_SUN);
+        sun.radius = Some(0.00465);
+        let mut rock = SpaceRock::from_xyz("rock", 1.2, -0.3, 0.1, 0.002, 0.015, -0.001, EPOCH);
+        rock.nongravs = Some(NonGravs::new(1e-13, 2e-6));
+        (rock.h, rock.g) = (Some(15.2), Some(0.15));
+        rock.covariance = Some(Covariance::Cartesian(Matrix6::from_fn(|i, j| if i == j { 1e-8 * (i + 1) as f64 } else { 1e-10 })));
+        let forces = ForceModel::new().with_nongravs().with_relativity(Relativity::EIH);
+        let integrator = Integrator::Mercurius(Mercurius::new(4.0).with_changeover(2.5).with_encounter_integrator(RK45::new(0.1, 1e-11)));
+        let handlers = Handlers::new().with_collision(CollisionHandler::Bounce).with_ejection(EjectionHandler::Halt, 100.0);
+        let mut simulation = Simulation::new(vec![sun, rock], forces, integrator).unwrap().with_handlers(handlers);
+        simulation.events.push(Event::Collision { epoch: EPOCH - 1.0, rocks: ("Sun".to_string(), "comet".to_string()) });
+        simulation.events.push(Event::Ejection { epoch: EPOCH - 0.5, rock: "stray".to_string(), distance: 1e3 });
+        simulation
+    }
+
+    #[test]
+    fn a_checkpoint_restores_the_simulation() {
+        let original = simulation();
+        let path = checkpoint("roundtrip");
+        original.save(&path).unwrap();
+        let restored = Simulation::load(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(restored.epoch, original.epoch);
+        assert_eq!(restored.events, original.events);
+        assert_eq!(restored.handlers, original.handlers);
+        assert_eq!(format!("{:?}", restored.forces), format!("{:?}", original.forces));
+        assert_eq!(format!("{:?}", restored.integrator), format!("{:?}", original.integrator));
+        assert_eq!(restored.rocks.len(), original.rocks.len());
+        for (a, b) in restored.rocks.iter().zip(&original.rocks) {
+            assert_eq!((&a.name, a.position, a.velocity, a.epoch), (&b.name, b.position, b.velocity, b.epoch));
+            assert_eq!((&a.frame, &a.origin, a.mass, a.radius), (&b.frame, &b.origin, b.mass, b.radius));
+            assert_eq!((a.nongravs, a.covariance), (b.nongravs, b.covariance));
+            assert_eq!((a.h, a.g), (b.h, b.g));
+        }
+    }
+
+    #[test]
+    fn a_corrupt_length_is_an_error() {
+        let mut bytes = MAGIC.to_vec();
+        put_f64(&mut bytes, EPOCH);
+        put_u64(&mut bytes, 1);
+        put_u64(&mut bytes, u64::MAX); // the length of the first rock's name
+        let path = checkpoint("corrupt");
+        File::create(&path).unwrap().write_all(&bytes).unwrap();
+        let error = Simulation::load(&path).err().unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(error.kind(), io::ErrorKind::UnexpectedEof);
+    }
+}
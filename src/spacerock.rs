@@ -1,22 +1,37 @@
 // access the constants from constants.rs in this directory
 use crate::constants::*;
+use crate::error::{checked, Result, SpaceRocksError};
+use crate::frame::Frame;
+use crate::designation;
 use crate::statevector::StateVector;
 use crate::observatory::Observatory;
 use crate::correct_for_ltt::correct_for_ltt;
+use crate::nongravs::NonGravs;
+use crate::keplerorbit::KeplerOrbit;
+use crate::covariance::{Covariance, ErrorEllipse};
+use crate::calc_xyz_from_kep::calc_xyz_from_kep;
+use crate::kepler_drift::kepler_drift;
+use crate::calc_kep_jacobian::{calc_kep_jacobian, calc_xyz_jacobian};
 
-use nalgebra::Vector3;
+use nalgebra::{Matrix2x3, Matrix6, Vector3, Vector6};
+use rand::Rng;
+use rand_distr::StandardNormal;
 
+#[derive(Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct SpaceRock {
     pub name: String,
     pub position: Vector3<f64>,
     pub velocity: Vector3<f64>,
     pub epoch: f64,
-    pub frame: String,
+    pub frame: Frame,
     pub origin: String,
-    pub mass: Option<f64>,
-    // pub radius: Option<f64>,
-    // pub H: Option<f64>,
-    // pub G: Option<f64>,
+    pub mass: Option<f64>, // GM in au^3/day^2
+    pub nongravs: Option<NonGravs>,
+    pub covariance: Option<Covariance>,
+    pub radius: Option<f64>, // physical radius in au
+    pub h: Option<f64>,      // absolute magnitude
+    pub g: Option<f64>,      // slope parameter of the HG system
     // pub mag: Option<f64>,
     // pub orbit: Option<KeplerOrbit>,
     // pub a: Option<f64>,
@@ -38,18 +53,25 @@ impl SpaceRock {
 
     // Instantiation Methods
 
-    pub fn from_spice(name: &str, epoch: f64) -> Self {
-        let et = spice::str2et(&format!("JD{epoch} UTC", epoch=epoch));
-        let (state, _) = spice::spkezr(name, et, "J2000", "NONE", "SSB");
-        SpaceRock {
+    pub fn from_spice(name: &str, epoch: f64) -> Result<Self> {
+        let (state, _) = checked(|| {
+            let et = spice::str2et(&format!("JD{epoch} UTC", epoch=epoch));
+            spice::spkezr(name, et, "J2000", "NONE", "SSB")
+        })?;
+        Ok(SpaceRock {
             name: name.to_string(), 
             position: Vector3::new(state[0], state[1], state[2]) * KM_TO_AU,
             velocity: Vector3::new(state[3], state[4], state[5]) * KM_TO_AU * SECONDS_PER_DAY,
             epoch: epoch,
-            frame: "J2000".to_string(),
+            frame: Frame::J2000,
             origin: "SSB".to_string(),
-            mass: None
-        }
+            mass: MASSES.get(&name.to_lowercase()).copied(),
+            nongravs: None,
+            covariance: None,
+            radius: None,
+            h: None,
+            g: None,
+        })
     }
 
     pub fn from_xyz(name: &str, x: f64, y: f64, z: f64, vx: f64, vy: f64, vz: f64, epoch: f64) -> Self {
@@ -58,9 +80,14 @@ impl SpaceRock {
             position: Vector3::new(x, y, z),
             velocity: Vector3::new(vx, vy, vz),
             epoch: epoch,
-            frame: "J2000".to_string(),
+            frame: Frame::J2000,
             origin: "SSB".to_string(),
-            mass: None
+            mass: None,
+            nongravs: None,
+            covariance: None,
+            radius: None,
+            h: None,
+            g: None,
         }
     }
 
@@ -70,30 +97,198 @@ impl SpaceRock {
             position: state.position,
             velocity: state.velocity,
             epoch: epoch,
-            frame: "J2000".to_string(),
+            frame: Frame::J2000,
             origin: "SSB".to_string(),
-            mass: None
+            mass: None,
+            nongravs: None,
+            covariance: None,
+            radius: None,
+            h: None,
+            g: None,
         }
     }
 
-    pub fn observe(&mut self, observer: &SpaceRock) -> [f64; 2] {
-        self.change_frame("J2000");
-        let corrected_rock = correct_for_ltt(&self, observer);
+    // Osculating heliocentric ecliptic elements, as the MPC and JPL publish them, taken about the sun's GM
+    // and recentred on the barycenter. Positions don't depend on GM, so only the velocity is rescaled.
+    pub fn from_heliocentric_elements(name: &str, orbit: KeplerOrbit, epoch: f64) -> Result<Self> {
+        let mut rock = SpaceRock::from_state(name, calc_xyz_from_kep(orbit), epoch);
+        rock.velocity *= (GM_SUN / MU_BARY).sqrt();
+        rock.frame = Frame::EclipJ2000;
+        rock.origin = "Sun".to_string();
+        rock.change_origin("SSB")?;
+        Ok(rock)
+    }
+
+    pub fn observe(&mut self, observer: &SpaceRock) -> Result<[f64; 2]> {
+        self.change_frame(&Frame::J2000)?;
+        Ok(self.sky_position(observer))
+    }
+
+    // Right ascension and declination as seen by the observer, for a rock already in J2000.
+    pub(crate) fn sky_position(&self, observer: &SpaceRock) -> [f64; 2] {
+        let corrected_rock = correct_for_ltt(self, observer);
         let ra = corrected_rock.position.y.atan2(corrected_rock.position.x);
         let dec = (corrected_rock.position.z / corrected_rock.position.norm()).asin();
-        return [ra, dec];
+        [ra, dec]
+    }
+
+    // Observe the rock, along with the uncertainty ellipse of its covariance on the sky.
+    pub fn observe_with_uncertainty(&mut self, observer: &SpaceRock) -> Result<([f64; 2], Option<ErrorEllipse>)> {
+        let [ra, dec] = self.observe(observer)?;
+        let ellipse = self.cartesian_covariance().map(|covariance| {
+            let rho = correct_for_ltt(self, observer).position;
+            let rho_xy_sq = rho.x * rho.x + rho.y * rho.y;
+            let rho_xy = rho_xy_sq.sqrt();
+            let rho_sq = rho.norm_squared();
+            // partials of (ra * cos(dec), dec) with respect to position
+            let jac = Matrix2x3::new(-rho.y / rho_xy_sq * dec.cos(), rho.x / rho_xy_sq * dec.cos(), 0.0,
+                                     -rho.x * rho.z / (rho_sq * rho_xy), -rho.y * rho.z / (rho_sq * rho_xy), rho_xy / rho_sq);
+            let sky = jac * covariance.fixed_view::<3, 3>(0, 0) * jac.transpose();
+            ErrorEllipse::from_covariance(&sky)
+        });
+        Ok(([ra, dec], ellipse))
+    }
+
+    pub fn change_frame(&mut self, frame: &Frame) -> Result<()> {
+        if *frame != self.frame {
+            let transformation = self.frame.transformation_to(frame, self.epoch)?;
+            self.transform(&transformation, frame);
+        }
+        Ok(())
     }
 
-    pub fn change_frame(&mut self, frame: &str) {
-        if frame != self.frame {
-            let inv = ROTATION_MATRICES[&self.frame].try_inverse().unwrap();
-            let rot = ROTATION_MATRICES[frame] * inv;
-            self.position = rot * self.position;
-            self.velocity = rot * self.velocity;
-            self.frame = frame.to_string();
+    // Apply a state transformation from the rock's frame into another, carrying the covariance along.
+    pub(crate) fn transform(&mut self, transformation: &Matrix6<f64>, frame: &Frame) {
+        let covariance = self.cartesian_covariance();
+        let state = transformation * Vector6::new(self.position.x, self.position.y, self.position.z,
+                                                  self.velocity.x, self.velocity.y, self.velocity.z);
+        self.position = Vector3::new(state[0], state[1], state[2]);
+        self.velocity = Vector3::new(state[3], state[4], state[5]);
+        self.frame = frame.clone();
+        if let Some(covariance) = covariance {
+            self.set_cartesian_covariance(transformation * covariance * transformation.transpose());
         }
     }
 
+    // Recentre the rock on another spice body, e.g. "Sun" or "Jupiter Barycenter". The shift is made in J2000,
+    // so a rotating frame is re-entered about the new origin. The ephemeris is taken as exact, leaving the
+    // covariance alone.
+    pub fn change_origin(&mut self, origin: &str) -> Result<()> {
+        if origin.eq_ignore_ascii_case(&self.origin) {
+            return Ok(());
+        }
+        let frame = self.frame.clone();
+        self.change_frame(&Frame::J2000)?;
+
+        let (position, velocity) = spice_offset(origin, &self.origin, self.epoch)?;
+        self.position -= position;
+        self.velocity -= velocity;
+        self.origin = origin.to_string();
+
+        self.change_frame(&frame)
+    }
+
+    // Two-body propagation about the barycentric mass, for barycentric rocks. It is made in J2000, so a
+    // rotating frame is re-entered at the new epoch. The covariance isn't propagated and is dropped.
+    pub fn analytic_propagate(&mut self, epoch: f64) -> Result<()> {
+        if !self.origin.eq_ignore_ascii_case("SSB") {
+            return Err(SpaceRocksError::InvalidInput(format!("{} must be barycentric to propagate analytically", self.name)));
+        }
+        let frame = self.frame.clone();
+        self.change_frame(&Frame::J2000)?;
+        self.drift(epoch);
+        self.change_frame(&frame)
+    }
+
+    pub(crate) fn drift(&mut self, epoch: f64) {
+        (self.position, self.velocity) = kepler_drift(&self.position, &self.velocity, MU_BARY, epoch - self.epoch);
+        self.epoch = epoch;
+        self.covariance = None;
+    }
+
+    // Replace the name with the canonical form of its designation, e.g. "K20A00A" or "(2020 AA)" with "2020 AA".
+    pub fn normalise_name(&mut self) -> Result<()> {
+        self.name = designation::normalise(&self.name)?;
+        Ok(())
+    }
+
+    pub fn state(&self) -> StateVector {
+        StateVector::new(self.position.x, self.position.y, self.position.z,
+                         self.velocity.x, self.velocity.y, self.velocity.z)
+    }
+
+    pub fn kepler_orbit(&self) -> KeplerOrbit {
+        KeplerOrbit::from_xyz(self.state())
+    }
+
+    // Osculating ecliptic elements about the sun's GM alone.
+    pub fn heliocentric_orbit(&self) -> Result<KeplerOrbit> {
+        let mut rock = self.clone();
+        rock.change_origin("Sun")?;
+        rock.change_frame(&Frame::EclipJ2000)?;
+        rock.velocity *= (MU_BARY / GM_SUN).sqrt();
+        Ok(rock.kepler_orbit())
+    }
+
+    pub fn cartesian_covariance(&self) -> Option<Matrix6<f64>> {
+        match self.covariance? {
+            Covariance::Cartesian(covariance) => Some(covariance),
+            Covariance::Keplerian(covariance) => {
+                let jac = calc_xyz_jacobian(self.kepler_orbit());
+                Some(jac * covariance * jac.transpose())
+            }
+        }
+    }
+
+    pub fn keplerian_covariance(&self) -> Option<Matrix6<f64>> {
+        match self.covariance? {
+            Covariance::Keplerian(covariance) => Some(covariance),
+            Covariance::Cartesian(covariance) => {
+                let jac = calc_kep_jacobian(self.state())?;
+                Some(jac * covariance * jac.transpose())
+            }
+        }
+    }
+
+    // Replace the covariance, keeping it in the representation it is already stored in.
+    pub fn set_cartesian_covariance(&mut self, covariance: Matrix6<f64>) {
+        self.covariance = match self.covariance {
+            Some(Covariance::Keplerian(_)) => calc_kep_jacobian(self.state())
+                .map(|jac| Covariance::Keplerian(jac * covariance * jac.transpose())),
+            _ => Some(Covariance::Cartesian(covariance)),
+        };
+    }
+
+    // Draw clones from the covariance, sampling in the representation it is stored in.
+    pub fn sample<R: Rng + ?Sized>(&self, n: usize, rng: &mut R) -> Option<Vec<SpaceRock>> {
+        let (covariance, keplerian) = match self.covariance? {
+            Covariance::Cartesian(covariance) => (covariance, false),
+            Covariance::Keplerian(covariance) => (covariance, true),
+        };
+        let l = covariance.cholesky()?.l();
+        let kep = self.kepler_orbit();
+
+        let mut clones = Vec::with_capacity(n);
+        for k in 0..n {
+            let z = Vector6::from_fn(|_, _| rng.sample::<f64, _>(StandardNormal));
+            let delta = l * z;
+            let mut clone = self.clone();
+            clone.name = format!("{}-{}", self.name, k);
+            clone.covariance = None;
+            if keplerian {
+                let state = calc_xyz_from_kep(KeplerOrbit::new(kep.a + delta[0], kep.e + delta[1], kep.inc + delta[2],
+                                                               kep.arg + delta[3], kep.node + delta[4], kep.f + delta[5]));
+                clone.position = state.position;
+                clone.velocity = state.velocity;
+            } else {
+                clone.position += Vector3::new(delta[0], delta[1], delta[2]);
+                clone.velocity += Vector3::new(delta[3], delta[4], delta[5]);
+            }
+            clones.push(clone);
+        }
+        Some(clones)
+    }
+
     fn r_squared(&self) -> f64 {
         self.position.dot(&self.position)
     }
@@ -112,6 +307,16 @@ impl SpaceRock {
 
 }
 
+// The state of a spice body relative to another, in J2000 with au and au/day.
+pub(crate) fn spice_offset(target: &str, observer: &str, epoch: f64) -> Result<(Vector3<f64>, Vector3<f64>)> {
+    let (state, _) = checked(|| {
+        let et = spice::str2et(&format!("JD{epoch} UTC", epoch=epoch));
+        spice::spkezr(target, et, "J2000", "NONE", observer)
+    })?;
+    Ok((Vector3::new(state[0], state[1], state[2]) * KM_TO_AU,
+        Vector3::new(state[3], state[4], state[5]) * KM_TO_AU * SECONDS_PER_DAY))
+}
+
 #[allow(dead_code)]
 fn separation(body1: &SpaceRock, body2: &SpaceRock) -> f64 {
     let d_pos = body1.position - body2.position;
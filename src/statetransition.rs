@@ -0,0 +1,30 @@
+use nalgebra::{Matrix6, Matrix6x2};
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct StateTransition {
+    pub state: Matrix6<f64>,      // d(final state) / d(initial state), states ordered x, y, z, vx, vy, vz
+    pub nongravs: Matrix6x2<f64>, // d(final state) / d(a2, area_to_mass)
+}
+
+impl StateTransition {
+
+    pub fn identity() -> Self {
+        StateTransition {
+            state: Matrix6::identity(),
+            nongravs: Matrix6x2::zeros(),
+        }
+    }
+
+    // Chain this transition onto an earlier one that ends where this one starts.
+    pub fn compose(&self, earlier: &StateTransition) -> Self {
+        StateTransition {
+            state: self.state * earlier.state,
+            nongravs: self.state * earlier.nongravs + self.nongravs,
+        }
+    }
+
+    // Map a covariance on the initial state to the final state.
+    pub fn propagate_covariance(&self, covariance: &Matrix6<f64>) -> Matrix6<f64> {
+        self.state * covariance * self.state.transpose()
+    }
+}
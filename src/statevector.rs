@@ -1,5 +1,7 @@
 use nalgebra::Vector3;
 
+#[derive(Clone, Copy, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct StateVector {
     pub position: Vector3<f64>,
     pub velocity: Vector3<f64>,
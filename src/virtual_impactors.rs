@@ -0,0 +1,221 @@
+use crate::spacerock::SpaceRock;
+use crate::frame::Frame;
+use crate::error::Result;
+use crate::forces::ForceModel;
+use crate::integrate::{integrate_with_encounters, RK45};
+use crate::encounter::{BPlane, EncounterRadius};
+use crate::constants::*;
+
+use nalgebra::Vector3;
+use rand::Rng;
+
+use std::f64::consts::PI;
+
+const EARTH_RADIUS: f64 = EQUAT_RAD * M_TO_AU;
+const DENSITY: f64 = 2600.0; // kg/m^3, assumed bulk density of impactors
+const MEGATON: f64 = 4.184e15; // J
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Sampling {
+    LineOfVariations(f64), // evenly spaced along the weak direction of the covariance, out to this many sigma
+    MonteCarlo,            // drawn from the full covariance
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct VirtualImpactor {
+    pub name: String,
+    pub sigma: Option<f64>, // position along the line of variations
+    pub epoch: f64,         // impact date
+    pub probability: f64,
+    pub bplane: BPlane,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct ImpactAssessment {
+    pub probability: f64,
+    pub virtual_impactors: Vec<VirtualImpactor>,
+    pub palermo: Option<f64>,
+    pub torino: u8,
+}
+
+// Sample the uncertainty region of a rock, integrate the samples to the end epoch and collect those that hit
+// the earth. The force model must include the earth, and the rock's diameter (km) sets the impact energy.
+// None if the rock has no usable covariance.
+pub fn find_virtual_impactors<R: Rng + ?Sized>(rock: &SpaceRock, forces: &ForceModel, integrator: &RK45, epoch: f64, n: usize, sampling: Sampling, diameter: f64, rng: &mut R) -> Result<Option<ImpactAssessment>> {
+
+    let mut rock = rock.clone();
+    rock.change_frame(&Frame::J2000)?;
+    let Some(covariance) = rock.cartesian_covariance() else {
+        return Ok(None);
+    };
+
+    let (mut clones, sigmas, weights) = match sampling {
+        Sampling::MonteCarlo => {
+            let Some(clones) = rock.sample(n, rng) else {
+                return Ok(None);
+            };
+            (clones, vec![None; n], vec![1.0 / n as f64; n])
+        }
+        Sampling::LineOfVariations(extent) => {
+            // linearised: the principal axis of the covariance with the largest spread. Positions and velocities
+            // are scaled by their standard deviations first, so the axis doesn't just follow the larger units.
+            let scale = covariance.diagonal().map(|variance| if variance > 0.0 { variance.sqrt() } else { 1.0 });
+            let correlation = covariance.component_div(&(scale * scale.transpose()));
+            let eigen = correlation.symmetric_eigen();
+            let widest = eigen.eigenvalues.imax();
+            let direction = eigen.eigenvectors.column(widest).component_mul(&scale) * eigen.eigenvalues[widest].max(0.0).sqrt();
+            let spacing = 2.0 * extent / (n.max(2) - 1) as f64;
+
+            let mut clones = Vec::with_capacity(n);
+            let mut sigmas = Vec::with_capacity(n);
+            let mut weights = Vec::with_capacity(n);
+            for k in 0..n {
+                // a single sample is the nominal orbit, carrying all of the probability
+                let (sigma, weight) = if n == 1 {
+                    (0.0, 1.0)
+                } else {
+                    let sigma = -extent + k as f64 * spacing;
+                    (sigma, (-0.5 * sigma * sigma).exp() / (2.0 * PI).sqrt() * spacing)
+                };
+                let mut clone = rock.clone();
+                clone.name = format!("{}-{}", rock.name, k);
+                clone.covariance = None;
+                clone.position += sigma * Vector3::new(direction[0], direction[1], direction[2]);
+                clone.velocity += sigma * Vector3::new(direction[3], direction[4], direction[5]);
+                clones.push(clone);
+                sigmas.push(Some(sigma));
+                weights.push(weight);
+            }
+            (clones, sigmas, weights)
+        }
+    };
+
+    let encounters = integrate_with_encounters(&mut clones, forces, integrator, epoch, EncounterRadius::Hill(1.0))?;
+
+    let mut virtual_impactors = Vec::new();
+    for (k, clone) in clones.iter().enumerate() {
+        let impact = encounters.iter()
+            .filter(|encounter| encounter.rock == clone.name && encounter.body.eq_ignore_ascii_case("earth"))
+            .filter_map(|encounter| encounter.bplane.map(|bplane| (encounter.epoch, bplane)))
+            .filter(|(_, bplane)| bplane.b < capture_radius(bplane.v_infinity))
+            .min_by(|a, b| a.0.total_cmp(&b.0));
+
+        if let Some((impact_epoch, bplane)) = impact {
+            virtual_impactors.push(VirtualImpactor {
+                name: clone.name.clone(),
+                sigma: sigmas[k],
+                epoch: impact_epoch,
+                probability: weights[k],
+                bplane,
+            });
+        }
+    }
+
+    let probability = virtual_impactors.iter().fold(0.0, |total, vi| total + vi.probability);
+
+    // the cumulative Palermo scale sums over the individual impact solutions
+    let palermo = if virtual_impactors.is_empty() {
+        None
+    } else {
+        let total: f64 = virtual_impactors.iter().map(|vi| {
+            let energy = impact_energy(diameter, vi.bplane.v_infinity);
+            10f64.powf(palermo_scale(vi.probability, energy, (vi.epoch - rock.epoch) / 365.25))
+        }).sum();
+        Some(total.log10())
+    };
+
+    let torino = virtual_impactors.iter().map(|vi| {
+        torino_scale(probability, impact_energy(diameter, vi.bplane.v_infinity))
+    }).max().unwrap_or(0);
+
+    Ok(Some(ImpactAssessment {
+        probability,
+        virtual_impactors,
+        palermo,
+        torino,
+    }))
+}
+
+// Largest impact parameter that still hits the earth once gravitational focusing is included.
+pub fn capture_radius(v_infinity: f64) -> f64 {
+    EARTH_RADIUS * (1.0 + 2.0 * GM_EARTH / (EARTH_RADIUS * v_infinity * v_infinity)).sqrt()
+}
+
+// Kinetic energy in megatons of a body of the given diameter (km) hitting the earth.
+pub fn impact_energy(diameter: f64, v_infinity: f64) -> f64 {
+    let radius = 0.5 * diameter * 1000.0;
+    let mass = DENSITY * 4.0 / 3.0 * PI * radius * radius * radius;
+    let v_impact_sq = v_infinity * v_infinity + 2.0 * GM_EARTH / EARTH_RADIUS;
+    let v_impact = v_impact_sq.sqrt() / (M_TO_AU * SECONDS_PER_DAY);
+    0.5 * mass * v_impact * v_impact / MEGATON
+}
+
+// Chesley et al. (2002), for an impact probability, energy in megatons and years until impact.
+pub fn palermo_scale(probability: f64, energy: f64, years: f64) -> f64 {
+    let background = 0.03 * energy.powf(-0.8);
+    (probability / (background * years)).log10()
+}
+
+// A coarse reading of the Binzel (2000) chart in log probability and log energy (megatons).
+pub fn torino_scale(probability: f64, energy: f64) -> u8 {
+
+    if probability <= 0.0 || energy < 1.0 {
+        return 0;
+    }
+
+    let log_p = probability.log10();
+    let log_e = energy.log10();
+
+    if log_p < -2.0 - log_e {
+        0
+    } else if probability < 1e-2 {
+        if log_e >= 5.0 { 2 } else { 1 }
+    } else if probability < 0.99 {
+        match (log_e < 2.0, log_e < 5.0, probability < 0.5) {
+            (true, _, _) => 3,
+            (false, true, true) => 4,
+            (false, true, false) => 5,
+            (false, false, true) => 6,
+            (false, false, false) => 7,
+        }
+    } else if log_e < 2.0 {
+        8
+    } else if log_e < 5.0 {
+        9
+    } else {
+        10
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // 99942 Apophis as posted on 27 December 2004: a 2.7% chance of impact in April 2029, some 24.3 years
+    // out, with an energy of about 1480 megatons, rated 1.10 on the Palermo scale and 4 on the Torino scale.
+    #[test]
+    fn apophis_in_december_2004() {
+        assert!((palermo_scale(0.027, 1480.0, 24.3) - 1.10).abs() < 0.02);
+        assert_eq!(torino_scale(0.027, 1480.0), 4);
+    }
+
+    // The background frequency is 0.03 E^-0.8 impacts a year, so matching it over the interval rates zero.
+    #[test]
+    fn the_background_rate_is_zero_on_the_palermo_scale() {
+        let years = 50.0;
+        let probability = 0.03 * 1e3f64.powf(-0.8) * years;
+        assert!(palermo_scale(probability, 1e3, years).abs() < 1e-12);
+        assert!((palermo_scale(0.01 * probability, 1e3, years) + 2.0).abs() < 1e-12);
+    }
+
+    // Corners of the Binzel (2000) chart: certain collisions rate 8 to 10 by energy, and small or
+    // unlikely ones rate zero.
+    #[test]
+    fn certain_and_negligible_collisions_on_the_torino_scale() {
+        assert_eq!(torino_scale(1.0, 10.0), 8);
+        assert_eq!(torino_scale(1.0, 1e3), 9);
+        assert_eq!(torino_scale(1.0, 1e8), 10);
+        assert_eq!(torino_scale(1.0, 0.5), 0);
+        assert_eq!(torino_scale(1e-8, 1e3), 0);
+    }
+}